@@ -0,0 +1,3 @@
+ALTER TABLE archives DROP CONSTRAINT IF EXISTS archives_name_key;
+ALTER TABLE archives ADD CONSTRAINT archives_repo_id_name_key UNIQUE (repo_id, name);
+CREATE INDEX IF NOT EXISTS archives_directory_id_idx ON archives (directory_id);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS retention_policies (
+    id UUID NOT NULL PRIMARY KEY,
+    root_directory_id UUID,
+    directory_id UUID,
+    keep_within VARCHAR,
+    keep_last INTEGER,
+    keep_daily INTEGER,
+    keep_weekly INTEGER,
+    keep_monthly INTEGER,
+    keep_yearly INTEGER,
+    CONSTRAINT fk_root_directories
+        FOREIGN KEY (root_directory_id)
+        REFERENCES root_directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT fk_directories
+        FOREIGN KEY (directory_id)
+        REFERENCES directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT ck_scope
+        CHECK (root_directory_id IS NULL OR directory_id IS NULL)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS repositories (
+    id UUID NOT NULL PRIMARY KEY,
+    label VARCHAR UNIQUE NOT NULL,
+    location VARCHAR UNIQUE NOT NULL,
+    repo_id VARCHAR UNIQUE NOT NULL,
+    encryption VARCHAR NOT NULL,
+    last_seen_date TIMESTAMP NOT NULL
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS drives (
+    id UUID NOT NULL PRIMARY KEY,
+    uuid VARCHAR UNIQUE NOT NULL,
+    label VARCHAR,
+    size BIGINT NOT NULL,
+    avail BIGINT NOT NULL,
+    last_mounted_date TIMESTAMP NOT NULL
+);
+
+ALTER TABLE repositories ADD COLUMN IF NOT EXISTS drive_id UUID;
+ALTER TABLE repositories ADD CONSTRAINT fk_drives
+    FOREIGN KEY (drive_id)
+    REFERENCES drives(id)
+    ON DELETE SET NULL;
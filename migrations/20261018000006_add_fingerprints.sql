@@ -0,0 +1,3 @@
+ALTER TABLE root_directories ADD COLUMN IF NOT EXISTS fingerprint VARCHAR NOT NULL DEFAULT 'metadata';
+ALTER TABLE directories ADD COLUMN IF NOT EXISTS fingerprint VARCHAR NOT NULL DEFAULT 'metadata';
+ALTER TABLE archives ADD COLUMN IF NOT EXISTS fingerprint VARCHAR NOT NULL DEFAULT 'metadata';
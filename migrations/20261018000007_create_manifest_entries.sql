@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS manifest_entries (
+    directory_id UUID NOT NULL,
+    archive_id UUID,
+    path VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    modified_date TIMESTAMP NOT NULL,
+    blake3_hash VARCHAR,
+    CONSTRAINT fk_directories
+        FOREIGN KEY (directory_id)
+        REFERENCES directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT fk_archives
+        FOREIGN KEY (archive_id)
+        REFERENCES archives(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS manifest_entries_directory_id_archive_id_idx
+    ON manifest_entries (directory_id, archive_id);
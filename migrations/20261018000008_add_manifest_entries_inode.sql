@@ -0,0 +1 @@
+ALTER TABLE manifest_entries ADD COLUMN IF NOT EXISTS inode BIGINT NOT NULL DEFAULT 0;
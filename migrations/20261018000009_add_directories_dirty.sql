@@ -0,0 +1 @@
+ALTER TABLE directories ADD COLUMN IF NOT EXISTS dirty BOOLEAN NOT NULL DEFAULT FALSE;
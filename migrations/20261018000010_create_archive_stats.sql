@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS archive_stats (
+    archive_id UUID NOT NULL PRIMARY KEY,
+    original_size BIGINT NOT NULL,
+    compressed_size BIGINT NOT NULL,
+    deduplicated_size BIGINT NOT NULL,
+    nfiles BIGINT NOT NULL,
+    duration DOUBLE PRECISION NOT NULL,
+    CONSTRAINT fk_archives
+        FOREIGN KEY (archive_id)
+        REFERENCES archives(id)
+        ON DELETE CASCADE
+);
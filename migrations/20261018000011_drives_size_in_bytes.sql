@@ -0,0 +1 @@
+UPDATE drives SET size = size * 1048576, avail = avail * 1048576;
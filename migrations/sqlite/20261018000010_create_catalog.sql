@@ -0,0 +1,120 @@
+CREATE TABLE IF NOT EXISTS root_directories (
+    id BLOB NOT NULL PRIMARY KEY,
+    path VARCHAR UNIQUE NOT NULL,
+    depth SMALLINT NOT NULL DEFAULT 0,
+    fingerprint VARCHAR NOT NULL DEFAULT 'metadata'
+);
+
+CREATE TABLE IF NOT EXISTS directories (
+    id BLOB NOT NULL PRIMARY KEY,
+    path VARCHAR UNIQUE NOT NULL,
+    blake3_hash VARCHAR NOT NULL,
+    root_directory_id BLOB NOT NULL,
+    fingerprint VARCHAR NOT NULL DEFAULT 'metadata',
+    dirty BOOLEAN NOT NULL DEFAULT FALSE,
+    CONSTRAINT fk_root_directories
+        FOREIGN KEY (root_directory_id)
+        REFERENCES root_directories(id)
+        ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS archives (
+    id BLOB NOT NULL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    repo_id VARCHAR NOT NULL,
+    archive_id VARCHAR NOT NULL,
+    created_date TIMESTAMP NOT NULL,
+    blake3_hash VARCHAR NOT NULL,
+    directory_id BLOB,
+    fingerprint VARCHAR NOT NULL DEFAULT 'metadata',
+    CONSTRAINT archives_repo_id_name_key UNIQUE (repo_id, name),
+    CONSTRAINT fk_directories
+        FOREIGN KEY (directory_id)
+        REFERENCES directories(id)
+        ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS archives_directory_id_idx ON archives (directory_id);
+
+CREATE TABLE IF NOT EXISTS retention_policies (
+    id BLOB NOT NULL PRIMARY KEY,
+    root_directory_id BLOB,
+    directory_id BLOB,
+    keep_within VARCHAR,
+    keep_last INTEGER,
+    keep_daily INTEGER,
+    keep_weekly INTEGER,
+    keep_monthly INTEGER,
+    keep_yearly INTEGER,
+    CONSTRAINT fk_root_directories
+        FOREIGN KEY (root_directory_id)
+        REFERENCES root_directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT fk_directories
+        FOREIGN KEY (directory_id)
+        REFERENCES directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT ck_scope
+        CHECK (root_directory_id IS NULL OR directory_id IS NULL)
+);
+
+CREATE TABLE IF NOT EXISTS drives (
+    id BLOB NOT NULL PRIMARY KEY,
+    uuid VARCHAR UNIQUE NOT NULL,
+    label VARCHAR,
+    size BIGINT NOT NULL,
+    avail BIGINT NOT NULL,
+    last_mounted_date TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS repositories (
+    id BLOB NOT NULL PRIMARY KEY,
+    label VARCHAR UNIQUE NOT NULL,
+    location VARCHAR UNIQUE NOT NULL,
+    repo_id VARCHAR UNIQUE NOT NULL,
+    encryption VARCHAR NOT NULL,
+    last_seen_date TIMESTAMP NOT NULL,
+    drive_id BLOB,
+    CONSTRAINT fk_drives
+        FOREIGN KEY (drive_id)
+        REFERENCES drives(id)
+        ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS replication_policies (
+    id BLOB NOT NULL PRIMARY KEY,
+    root_directory_id BLOB,
+    directory_id BLOB,
+    copies SMALLINT NOT NULL,
+    CONSTRAINT fk_root_directories
+        FOREIGN KEY (root_directory_id)
+        REFERENCES root_directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT fk_directories
+        FOREIGN KEY (directory_id)
+        REFERENCES directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT ck_scope
+        CHECK (root_directory_id IS NULL OR directory_id IS NULL)
+);
+
+CREATE TABLE IF NOT EXISTS manifest_entries (
+    directory_id BLOB NOT NULL,
+    archive_id BLOB,
+    path VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    modified_date TIMESTAMP NOT NULL,
+    inode BIGINT NOT NULL DEFAULT 0,
+    blake3_hash VARCHAR,
+    CONSTRAINT fk_directories
+        FOREIGN KEY (directory_id)
+        REFERENCES directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT fk_archives
+        FOREIGN KEY (archive_id)
+        REFERENCES archives(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS manifest_entries_directory_id_archive_id_idx
+    ON manifest_entries (directory_id, archive_id);
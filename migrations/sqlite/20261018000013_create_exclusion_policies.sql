@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS exclusion_policies (
+    id BLOB NOT NULL PRIMARY KEY,
+    root_directory_id BLOB,
+    directory_id BLOB,
+    patterns TEXT NOT NULL,
+    CONSTRAINT fk_root_directories
+        FOREIGN KEY (root_directory_id)
+        REFERENCES root_directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT fk_directories
+        FOREIGN KEY (directory_id)
+        REFERENCES directories(id)
+        ON DELETE CASCADE,
+    CONSTRAINT ck_scope
+        CHECK (root_directory_id IS NULL OR directory_id IS NULL)
+);
@@ -0,0 +1,241 @@
+use super::borg::Borg;
+use super::model::RetentionPolicy;
+use super::restic::Restic;
+use super::tarball::Tarball;
+use async_std::io::{Error, ErrorKind};
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDateTime};
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+
+pub const BORG: &str = "borg";
+pub const RESTIC: &str = "restic";
+pub const TAR: &str = "tar";
+pub const ARCHIVERS: [&str; 3] = [BORG, RESTIC, TAR];
+
+mod datetime_format {
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
+    pub fn serialize<S>(date: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let s = format!("{}", date.format(DATETIME_FORMAT));
+        serializer.serialize_str(&s)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        NaiveDateTime::parse_from_str(&s, DATETIME_FORMAT).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Archive {
+    pub id: String,
+    pub name: String,
+    #[serde(with = "datetime_format")]
+    pub start: NaiveDateTime,
+    #[serde(default)]
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub stats: Option<Stats>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Stats {
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub deduplicated_size: u64,
+    pub nfiles: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Encryption {
+    pub mode: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Repository {
+    pub id: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CreateOutput {
+    pub archive: Archive,
+    pub repository: Repository,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct InfoOutput {
+    pub encryption: Encryption,
+    pub repository: Repository,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ListOutput {
+    pub archives: Vec<Archive>,
+    pub repository: Repository,
+}
+
+#[async_trait]
+pub trait Archiver: Send + Sync {
+    async fn init(&self, repo: &str, encryption: &str) -> Result<(), Error>;
+    async fn info(&self, repo: &str) -> Result<InfoOutput, Error>;
+    async fn list(&self, repo: &str) -> Result<ListOutput, Error>;
+    async fn info_archive(&self, repo: &str, name: &str) -> Result<Archive, Error>;
+    /// `patterns` are the gitignore-style patterns relative to `path` of
+    /// `Filter::patterns`, directories tagged with a `CACHEDIR.TAG` are left
+    /// out as well.
+    #[allow(clippy::too_many_arguments)]
+    async fn create(
+        &self,
+        repo: &str,
+        prefix: &str,
+        path: &str,
+        patterns: &[String],
+        compression: &str,
+        dry_run: bool,
+        progress: &ProgressBar,
+    ) -> Result<CreateOutput, Error>;
+    /// Whether `create` reports the statistics of the archive it would write
+    /// on a dry run, so they can be used to estimate its size.
+    fn reports_dry_run_stats(&self) -> bool {
+        false
+    }
+    async fn delete(&self, repo: &str, name: &str, dry_run: bool) -> Result<(), Error>;
+    async fn prune(
+        &self,
+        repo: &str,
+        prefix: &str,
+        retention_policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<(), Error>;
+    async fn extract(&self, repo: &str, name: &str, path: &str, dry_run: bool)
+        -> Result<(), Error>;
+    async fn check(&self, repo: &str, name: &str, repair: bool) -> Result<(), Error>;
+    /// Transfers every archive of `other_repo`, or only the one called `name`.
+    async fn transfer(
+        &self,
+        repo: &str,
+        other_repo: &str,
+        name: Option<&str>,
+        dry_run: bool,
+    ) -> Result<(), Error>;
+}
+
+pub fn new(archiver: &str, volume_size: Option<u64>) -> Result<Box<dyn Archiver>, Error> {
+    match archiver {
+        BORG => Ok(Box::new(Borg::new())),
+        RESTIC => Ok(Box::new(Restic)),
+        TAR => Ok(Box::new(Tarball { volume_size })),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unsupported archiver {}", archiver),
+        )),
+    }
+}
+
+/// Parses a borg style `--keep-within` interval, in which a month is 31
+/// days and a year 365.
+pub fn parse_keep_within(keep_within: &str) -> Result<Duration, Error> {
+    let error = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid keep-within interval {}", keep_within),
+        )
+    };
+    let (count, unit) = keep_within.split_at(keep_within.len().saturating_sub(1));
+    let count = count.parse::<i64>().map_err(|_| error())?;
+    match unit {
+        "H" => Ok(Duration::hours(count)),
+        "d" => Ok(Duration::days(count)),
+        "w" => Ok(Duration::weeks(count)),
+        "m" => Ok(Duration::days(count * 31)),
+        "y" => Ok(Duration::days(count * 365)),
+        _ => Err(error()),
+    }
+}
+
+/// Formats the `--keep-*` options of borg and restic, with `keep_within`
+/// already in the archiver's own interval syntax.
+pub fn get_retention_options(
+    retention_policy: &RetentionPolicy,
+    keep_within: Option<&str>,
+) -> Vec<String> {
+    let mut options: Vec<String> = Vec::new();
+    if let Some(keep_within) = keep_within {
+        options.push(format!("--keep-within={}", keep_within));
+    }
+    if let Some(keep_last) = retention_policy.keep_last {
+        options.push(format!("--keep-last={}", keep_last));
+    }
+    if let Some(keep_daily) = retention_policy.keep_daily {
+        options.push(format!("--keep-daily={}", keep_daily));
+    }
+    if let Some(keep_weekly) = retention_policy.keep_weekly {
+        options.push(format!("--keep-weekly={}", keep_weekly));
+    }
+    if let Some(keep_monthly) = retention_policy.keep_monthly {
+        options.push(format!("--keep-monthly={}", keep_monthly));
+    }
+    if let Some(keep_yearly) = retention_policy.keep_yearly {
+        options.push(format!("--keep-yearly={}", keep_yearly));
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn parse_keep_within_units() {
+        assert_eq!(parse_keep_within("12H").unwrap(), Duration::hours(12));
+        assert_eq!(parse_keep_within("2d").unwrap(), Duration::days(2));
+        assert_eq!(parse_keep_within("1w").unwrap(), Duration::weeks(1));
+        assert_eq!(parse_keep_within("3m").unwrap(), Duration::days(93));
+        assert_eq!(parse_keep_within("1y").unwrap(), Duration::days(365));
+    }
+
+    #[test]
+    fn parse_keep_within_rejects_invalid_intervals() {
+        for keep_within in &["", "d", "5", "5x", "1.5d", "d5"] {
+            assert_eq!(
+                parse_keep_within(keep_within).unwrap_err().kind(),
+                ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn get_retention_options_formats_set_rules() {
+        let retention_policy = RetentionPolicy {
+            id: Uuid::new_v4(),
+            root_directory_id: None,
+            directory_id: None,
+            keep_within: Some(String::from("2w")),
+            keep_last: Some(3),
+            keep_daily: None,
+            keep_weekly: Some(4),
+            keep_monthly: None,
+            keep_yearly: Some(1),
+        };
+        assert_eq!(
+            get_retention_options(&retention_policy, Some("336h")),
+            vec![
+                "--keep-within=336h",
+                "--keep-last=3",
+                "--keep-weekly=4",
+                "--keep-yearly=1"
+            ]
+        );
+    }
+}
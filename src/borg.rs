@@ -1,125 +1,266 @@
+use super::archiver::{
+    get_retention_options, Archive, Archiver, CreateOutput, Encryption, InfoOutput, ListOutput,
+    Repository, Stats,
+};
+use super::exclusion::Pattern;
+use super::model::RetentionPolicy;
+use super::progress;
 use async_std::fs;
-use async_std::io::Error;
+use async_std::io::{BufReader, Error, ErrorKind};
 use async_std::path::Path;
-use async_std::process::{Command, Stdio};
-use chrono::NaiveDateTime;
-use log::debug;
-use serde::{Deserialize, Serialize};
+use async_std::prelude::*;
+use async_std::process::{ChildStderr, Command, ExitStatus, Stdio};
+use async_std::task;
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use regex::Regex;
+use serde::Deserialize;
+use std::os::unix::process::ExitStatusExt;
 
-mod datetime_format {
-    use chrono::NaiveDateTime;
-    use serde::{Deserialize, Deserializer, Serializer};
+lazy_static! {
+    static ref RE: Regex = Regex::new(r"^borg(?:\.exe)? (?P<major>[0-9]+)\.").unwrap();
+    static ref REPOSITORY_VERSION_RE: Regex =
+        Regex::new(r"(?m)^version\s*=\s*(?P<version>[0-9]+)").unwrap();
+    static ref UNSUPPORTED_VERSION_RE: Regex = Regex::new(
+        r"(?i)unsupported repository version|repository version [0-9]+ is not supported"
+    )
+    .unwrap();
+}
 
-    const DATETIME_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S%.6f";
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
 
-    pub fn serialize<S>(date: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let s = format!("{}", date.format(DATETIME_FORMAT));
-        serializer.serialize_str(&s)
-    }
+#[derive(Deserialize)]
+struct Archive2 {
+    id: String,
+    name: String,
+    start: Option<String>,
+    time: Option<String>,
+    duration: Option<f64>,
+    stats: Option<Stats>,
+}
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        NaiveDateTime::parse_from_str(&s, DATETIME_FORMAT).map_err(serde::de::Error::custom)
-    }
+#[derive(Deserialize)]
+struct CreateOutput2 {
+    archive: Archive2,
+    repository: Repository,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct Archive {
-    pub id: String,
-    pub name: String,
-    #[serde(with = "datetime_format")]
-    pub start: NaiveDateTime,
+#[derive(Deserialize)]
+struct ListOutput2 {
+    archives: Vec<Archive2>,
+    repository: Repository,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct Encryption {
-    mode: String,
+#[derive(Deserialize)]
+struct InfoOutput2 {
+    encryption: Encryption,
+    repository: Repository,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct Repository {
-    pub id: String,
-    #[serde(with = "datetime_format")]
-    last_modified: NaiveDateTime,
-    location: String,
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LogLine {
+    ArchiveProgress {
+        original_size: Option<u64>,
+        nfiles: Option<u64>,
+        #[serde(default)]
+        finished: bool,
+    },
+    ProgressPercent {
+        current: Option<u64>,
+        total: Option<u64>,
+        message: Option<String>,
+        #[serde(default)]
+        finished: bool,
+    },
+    LogMessage {
+        levelname: String,
+        message: String,
+        msgid: Option<String>,
+    },
+    #[serde(other)]
+    Other,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct CreateOutput {
-    pub archive: Archive,
-    pub repository: Repository,
+pub struct Borg {
+    pub major: u8,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct InfoOutput {
-    pub repository: Repository,
+impl Borg {
+    pub fn new() -> Borg {
+        let output = std::process::Command::new("borg").arg("--version").output();
+        let major = output
+            .ok()
+            .and_then(|output| {
+                RE.captures(String::from_utf8_lossy(&output.stdout).trim())
+                    .and_then(|caps| caps.name("major").unwrap().as_str().parse::<u8>().ok())
+            })
+            .unwrap_or(1);
+        debug!("Detected borg {}.x", major);
+        Borg { major }
+    }
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct ListOutput {
-    pub archives: Vec<Archive>,
-    encryption: Encryption,
-    pub repository: Repository,
+#[async_trait]
+impl Archiver for Borg {
+    async fn init(&self, repo: &str, encryption: &str) -> Result<(), Error> {
+        match self.major {
+            1 => init(repo, encryption).await,
+            _ => init2(repo, encryption).await,
+        }
+    }
+
+    async fn info(&self, repo: &str) -> Result<InfoOutput, Error> {
+        match self.major {
+            1 => info(repo).await,
+            _ => info2(repo).await,
+        }
+    }
+
+    async fn list(&self, repo: &str) -> Result<ListOutput, Error> {
+        match self.major {
+            1 => list(repo).await,
+            _ => list2(repo).await,
+        }
+    }
+
+    async fn info_archive(&self, repo: &str, name: &str) -> Result<Archive, Error> {
+        match self.major {
+            1 => info_archive(repo, name).await,
+            _ => info_archive2(repo, name).await,
+        }
+    }
+
+    async fn create(
+        &self,
+        repo: &str,
+        prefix: &str,
+        path: &str,
+        patterns: &[String],
+        compression: &str,
+        dry_run: bool,
+        progress: &ProgressBar,
+    ) -> Result<CreateOutput, Error> {
+        match self.major {
+            1 => create(repo, prefix, path, patterns, compression, dry_run, progress).await,
+            _ => create2(repo, prefix, path, patterns, compression, dry_run, progress).await,
+        }
+    }
+
+    async fn delete(&self, repo: &str, name: &str, dry_run: bool) -> Result<(), Error> {
+        match self.major {
+            1 => delete(repo, name, dry_run).await,
+            _ => delete2(repo, name, dry_run).await,
+        }
+    }
+
+    async fn prune(
+        &self,
+        repo: &str,
+        prefix: &str,
+        retention_policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        match self.major {
+            1 => prune(repo, prefix, retention_policy, dry_run).await,
+            _ => prune2(repo, prefix, retention_policy, dry_run).await,
+        }
+    }
+
+    async fn extract(
+        &self,
+        repo: &str,
+        name: &str,
+        path: &str,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        match self.major {
+            1 => extract(repo, name, path, dry_run).await,
+            _ => extract2(repo, name, path, dry_run).await,
+        }
+    }
+
+    async fn check(&self, repo: &str, name: &str, repair: bool) -> Result<(), Error> {
+        match self.major {
+            1 => check(repo, name, repair).await,
+            _ => check2(repo, name, repair).await,
+        }
+    }
+
+    async fn transfer(
+        &self,
+        repo: &str,
+        other_repo: &str,
+        name: Option<&str>,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        match self.major {
+            1 => Err(Error::new(
+                ErrorKind::Unsupported,
+                "Transferring archives requires borg 2",
+            )),
+            _ => transfer2(repo, other_repo, name, dry_run).await,
+        }
+    }
 }
 
-pub async fn init(repo: &str, encryption: &str) -> Result<(), Error> {
+async fn init(repo: &str, encryption: &str) -> Result<(), Error> {
     if !Path::new(repo).is_dir().await {
         fs::create_dir_all(repo).await?;
     }
-    let mut args: Vec<&str> = Vec::new();
-    args.push("init");
-    args.push("--progress");
-    args.push("--encryption");
-    args.push(encryption);
-    args.push(repo);
-    let mut command = Command::new("borg");
-    command
-        .args(&args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    let args: Vec<&str> = vec!["init", "--progress", "--encryption", encryption, repo];
+    let mut command = borg_command();
+    command.args(&args);
     debug!("{:?}", command);
-    command.status().await?;
+    status(&mut command).await?;
     Ok(())
 }
 
-pub async fn info(repo: &str) -> Result<InfoOutput, Error> {
-    let mut args: Vec<&str> = Vec::new();
-    args.push("info");
-    args.push("--json");
-    args.push(repo);
-    let mut command = Command::new("borg");
+async fn info(repo: &str) -> Result<InfoOutput, Error> {
+    let args: Vec<&str> = vec!["info", "--json", repo];
+    let mut command = borg_command();
     command.args(&args);
     debug!("{:?}", command);
-    let output = command.output().await?;
-    let info_output = serde_json::from_slice(output.stdout.as_slice())?;
+    let stdout = output(&mut command, None).await?;
+    let info_output = serde_json::from_slice(stdout.as_slice())?;
     Ok(info_output)
 }
 
-pub async fn list(repo: &str) -> Result<ListOutput, Error> {
-    let mut args: Vec<&str> = Vec::new();
-    args.push("list");
-    args.push("--json");
-    args.push(repo);
-    let mut command = Command::new("borg");
+async fn list(repo: &str) -> Result<ListOutput, Error> {
+    let args: Vec<&str> = vec!["list", "--json", repo];
+    let mut command = borg_command();
     command.args(&args);
     debug!("{:?}", command);
-    let output = command.output().await?;
-    let list_output = serde_json::from_slice(output.stdout.as_slice())?;
+    let stdout = output(&mut command, None).await?;
+    let list_output = serde_json::from_slice(stdout.as_slice())?;
     Ok(list_output)
 }
 
-pub async fn create(
+async fn info_archive(repo: &str, name: &str) -> Result<Archive, Error> {
+    let repo_name = format!("{}::{}", repo, name);
+    let args: Vec<&str> = vec!["info", "--json", &repo_name];
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    let stdout = output(&mut command, None).await?;
+    let info_output: ListOutput = serde_json::from_slice(stdout.as_slice())?;
+    info_output
+        .archives
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("No archive {}", name)))
+}
+
+async fn create(
     repo: &str,
     prefix: &str,
     path: &str,
+    patterns: &[String],
     compression: &str,
     dry_run: bool,
+    progress: &ProgressBar,
 ) -> Result<CreateOutput, Error> {
     let repo_name = format!("{}::{}-{{utcnow:%Y%m%d-%H%M%S}}", repo, prefix);
     let mut args: Vec<&str> = Vec::new();
@@ -135,95 +276,705 @@ pub async fn create(
     args.push("--noacls");
     args.push("--nobsdflags");
     args.push("--noxattrs");
+    args.push("--exclude-caches");
+    let patterns = get_patterns(patterns);
+    for pattern in &patterns {
+        args.push("--pattern");
+        args.push(pattern);
+    }
     args.push(&repo_name);
     args.push(".");
-    let mut command = Command::new("borg");
-    command
-        .current_dir(path)
-        .args(&args)
-        .stderr(Stdio::inherit());
+    let mut command = borg_command();
+    command.current_dir(path).args(&args);
     debug!("{:?}", command);
-    let output = command.output().await?;
-    let create_output = serde_json::from_slice(output.stdout.as_slice())?;
+    let stdout = output(&mut command, Some(progress)).await?;
+    if dry_run {
+        return Ok(get_dry_run_output(prefix, info(repo).await?.repository));
+    }
+    let create_output = serde_json::from_slice(stdout.as_slice())?;
     Ok(create_output)
 }
 
-pub async fn delete(repo: &str, prefix: &str, dry_run: bool) -> Result<(), Error> {
+/// Borg saves no archive on a dry run and prints neither its JSON nor statistics.
+fn get_dry_run_output(prefix: &str, repository: Repository) -> CreateOutput {
+    let start = Utc::now();
+    CreateOutput {
+        archive: Archive {
+            id: String::new(),
+            name: format!("{}-{}", prefix, start.format("%Y%m%d-%H%M%S")),
+            start: start.with_timezone(&Local).naive_local(),
+            duration: None,
+            stats: None,
+        },
+        repository,
+    }
+}
+
+async fn delete(repo: &str, name: &str, dry_run: bool) -> Result<(), Error> {
+    let repo_name = format!("{}::{}", repo, name);
     let mut args: Vec<&str> = Vec::new();
     args.push("delete");
     if dry_run {
         args.push("--dry-run");
     }
     args.push("--progress");
-    args.push("--prefix");
-    args.push(&prefix);
-    args.push(&repo);
-    let mut command = Command::new("borg");
-    command
-        .args(&args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    args.push(&repo_name);
+    let mut command = borg_command();
+    command.args(&args);
     debug!("{:?}", command);
-    command.status().await?;
+    status(&mut command).await?;
     Ok(())
 }
 
-pub async fn prune(repo: &str, prefix: &str, dry_run: bool) -> Result<(), Error> {
+async fn prune(
+    repo: &str,
+    prefix: &str,
+    retention_policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let glob_archives = format!("{}-????????-??????", prefix);
+    let options = get_retention_options(retention_policy, retention_policy.keep_within.as_deref());
     let mut args: Vec<&str> = Vec::new();
     args.push("prune");
+    args.push("--progress");
     if dry_run {
         args.push("--dry-run");
     }
-    args.push("--keep-last");
-    args.push("1");
-    args.push("--prefix");
-    args.push(prefix);
-    args.push(&repo);
-    let mut command = Command::new("borg");
-    command
-        .args(&args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    args.push("--list");
+    args.extend(options.iter().map(String::as_str));
+    args.push("--glob-archives");
+    args.push(&glob_archives);
+    args.push(repo);
+    let mut command = borg_command();
+    command.args(&args);
     debug!("{:?}", command);
-    command.status().await?;
+    status(&mut command).await?;
     Ok(())
 }
 
-pub async fn extract(repo: &str, name: &str, path: &str, dry_run: bool) -> Result<(), Error> {
+async fn extract(repo: &str, name: &str, path: &str, dry_run: bool) -> Result<(), Error> {
     if !Path::new(path).is_dir().await {
         fs::create_dir_all(path).await?;
     }
     let repo_name = format!("{}::{}", repo, name);
     let mut args: Vec<&str> = Vec::new();
     args.push("extract");
+    args.push("--progress");
     if dry_run {
         args.push("--dry-run");
     }
     args.push(&repo_name);
-    let mut command = Command::new("borg");
-    command
-        .current_dir(path)
-        .args(&args)
-        .stderr(Stdio::inherit());
+    let mut command = borg_command();
+    command.current_dir(path).args(&args);
     debug!("{:?}", command);
-    command.status().await?;
+    status(&mut command).await?;
     Ok(())
 }
 
-pub async fn check(repo: &str, name: &str, repair: bool) -> Result<(), Error> {
+async fn check(repo: &str, name: &str, repair: bool) -> Result<(), Error> {
     let repo_name = format!("{}::{}", repo, name);
     let mut args: Vec<&str> = Vec::new();
     args.push("check");
+    args.push("--progress");
     if repair {
         args.push("--repair");
     }
     args.push(&repo_name);
-    let mut command = Command::new("borg");
-    command
-        .args(&args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    status(&mut command).await?;
+    Ok(())
+}
+
+async fn init2(repo: &str, encryption: &str) -> Result<(), Error> {
+    let args: Vec<&str> = vec![
+        "rcreate",
+        "--progress",
+        "--encryption",
+        encryption,
+        "--repo",
+        repo,
+    ];
+    let mut command = borg_command();
+    command.args(&args);
     debug!("{:?}", command);
-    command.status().await?;
+    status(&mut command).await?;
     Ok(())
 }
+
+async fn info2(repo: &str) -> Result<InfoOutput, Error> {
+    let args: Vec<&str> = vec!["rinfo", "--json", "--repo", repo];
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    let stdout = output(&mut command, None).await?;
+    let info_output: InfoOutput2 = serde_json::from_slice(stdout.as_slice())?;
+    Ok(InfoOutput {
+        encryption: info_output.encryption,
+        repository: info_output.repository,
+    })
+}
+
+async fn list2(repo: &str) -> Result<ListOutput, Error> {
+    let args: Vec<&str> = vec!["rlist", "--json", "--repo", repo];
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    let stdout = output(&mut command, None).await?;
+    let list_output: ListOutput2 = serde_json::from_slice(stdout.as_slice())?;
+    Ok(ListOutput {
+        archives: list_output
+            .archives
+            .into_iter()
+            .map(convert_archive)
+            .collect::<Result<Vec<Archive>, Error>>()?,
+        repository: list_output.repository,
+    })
+}
+
+async fn info_archive2(repo: &str, name: &str) -> Result<Archive, Error> {
+    let args: Vec<&str> = vec!["info", "--json", "--repo", repo, name];
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    let stdout = output(&mut command, None).await?;
+    let info_output: ListOutput2 = serde_json::from_slice(stdout.as_slice())?;
+    info_output
+        .archives
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("No archive {}", name)))
+        .and_then(convert_archive)
+}
+
+async fn create2(
+    repo: &str,
+    prefix: &str,
+    path: &str,
+    patterns: &[String],
+    compression: &str,
+    dry_run: bool,
+    progress: &ProgressBar,
+) -> Result<CreateOutput, Error> {
+    let name = format!("{}-{{utcnow:%Y%m%d-%H%M%S}}", prefix);
+    let mut args: Vec<&str> = Vec::new();
+    args.push("create");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--json");
+    args.push("--progress");
+    args.push("--compression");
+    args.push(compression);
+    args.push("--noatime");
+    args.push("--noacls");
+    args.push("--noflags");
+    args.push("--noxattrs");
+    args.push("--exclude-caches");
+    let patterns = get_patterns(patterns);
+    for pattern in &patterns {
+        args.push("--pattern");
+        args.push(pattern);
+    }
+    args.push("--repo");
+    args.push(repo);
+    args.push(&name);
+    args.push(".");
+    let mut command = borg_command();
+    command.current_dir(path).args(&args);
+    debug!("{:?}", command);
+    let stdout = output(&mut command, Some(progress)).await?;
+    if dry_run {
+        return Ok(get_dry_run_output(prefix, info2(repo).await?.repository));
+    }
+    let create_output: CreateOutput2 = serde_json::from_slice(stdout.as_slice())?;
+    Ok(CreateOutput {
+        archive: convert_archive(create_output.archive)?,
+        repository: create_output.repository,
+    })
+}
+
+async fn delete2(repo: &str, name: &str, dry_run: bool) -> Result<(), Error> {
+    let match_archives = format!("sh:{}", name);
+    let mut args: Vec<&str> = Vec::new();
+    args.push("delete");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--progress");
+    args.push("--repo");
+    args.push(repo);
+    args.push("--match-archives");
+    args.push(&match_archives);
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    status(&mut command).await?;
+    Ok(())
+}
+
+async fn prune2(
+    repo: &str,
+    prefix: &str,
+    retention_policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let match_archives = format!("sh:{}-????????-??????", prefix);
+    let options = get_retention_options(retention_policy, retention_policy.keep_within.as_deref());
+    let mut args: Vec<&str> = Vec::new();
+    args.push("prune");
+    args.push("--progress");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--list");
+    args.extend(options.iter().map(String::as_str));
+    args.push("--match-archives");
+    args.push(&match_archives);
+    args.push("--repo");
+    args.push(repo);
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    status(&mut command).await?;
+    Ok(())
+}
+
+async fn extract2(repo: &str, name: &str, path: &str, dry_run: bool) -> Result<(), Error> {
+    if !Path::new(path).is_dir().await {
+        fs::create_dir_all(path).await?;
+    }
+    let mut args: Vec<&str> = Vec::new();
+    args.push("extract");
+    args.push("--progress");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--repo");
+    args.push(repo);
+    args.push(name);
+    let mut command = borg_command();
+    command.current_dir(path).args(&args);
+    debug!("{:?}", command);
+    status(&mut command).await?;
+    Ok(())
+}
+
+async fn check2(repo: &str, name: &str, repair: bool) -> Result<(), Error> {
+    let match_archives = format!("sh:{}", name);
+    let mut args: Vec<&str> = Vec::new();
+    args.push("check");
+    args.push("--progress");
+    if repair {
+        args.push("--repair");
+    }
+    args.push("--repo");
+    args.push(repo);
+    args.push("--match-archives");
+    args.push(&match_archives);
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    status(&mut command).await?;
+    Ok(())
+}
+
+async fn transfer2(
+    repo: &str,
+    other_repo: &str,
+    name: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let mut args: Vec<&str> = Vec::new();
+    args.push("transfer");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    if is_borg1_repository(other_repo).await? {
+        args.push("--upgrader");
+        args.push("From12To20");
+    }
+    let match_archives = name.map(|name| format!("sh:{}", name));
+    if let Some(match_archives) = &match_archives {
+        args.push("--match-archives");
+        args.push(match_archives);
+    }
+    args.push("--other-repo");
+    args.push(other_repo);
+    args.push("--repo");
+    args.push(repo);
+    let mut command = borg_command();
+    command.args(&args);
+    debug!("{:?}", command);
+    status(&mut command).await?;
+    Ok(())
+}
+
+/// Local repositories record their version in `config`, remote ones are
+/// probed: borg 2 refuses to open a borg 1 repository.
+async fn is_borg1_repository(repo: &str) -> Result<bool, Error> {
+    if let Ok(config) = fs::read_to_string(Path::new(repo).join("config")).await {
+        if let Some(caps) = REPOSITORY_VERSION_RE.captures(&config) {
+            return Ok(&caps["version"] == "1");
+        }
+    }
+    match info2(repo).await {
+        Ok(_) => Ok(false),
+        Err(error) if is_unsupported_version(&error) => Ok(true),
+        Err(error) => Err(error),
+    }
+}
+
+fn is_unsupported_version(error: &Error) -> bool {
+    UNSUPPORTED_VERSION_RE.is_match(&error.to_string())
+}
+
+fn get_patterns(patterns: &[String]) -> Vec<String> {
+    // The first matching pattern wins in borg, the last one in gitignore
+    patterns
+        .iter()
+        .rev()
+        .filter_map(|line| Pattern::parse(line))
+        .map(|pattern| {
+            let kind = if pattern.negated { '+' } else { '-' };
+            format!("{} re:{}", kind, get_regex(&pattern))
+        })
+        .collect()
+}
+
+fn get_regex(pattern: &Pattern) -> String {
+    // Archived paths are relative to `.`, with or without a leading `./`
+    let mut regex = String::from("^(\\./)?");
+    if !pattern.anchored {
+        regex.push_str("(.*/)?");
+    }
+    let glob: Vec<char> = pattern.glob.chars().collect();
+    let mut i = 0;
+    while i < glob.len() {
+        match glob[i] {
+            '*' if glob.get(i + 1) == Some(&'*') => {
+                if glob.get(i + 2) == Some(&'/') {
+                    regex.push_str("(.*/)?");
+                    i += 1;
+                } else {
+                    regex.push_str(".*");
+                }
+                i += 1;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match get_bracket_regex(&glob, i) {
+                Some((bracket, end)) => {
+                    regex.push_str(&bracket);
+                    i = end;
+                }
+                None => regex.push_str("\\["),
+            },
+            '\\' if i + 1 < glob.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&glob[i].to_string()));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex.push_str(if pattern.directory_only { "/" } else { "(/|$)" });
+    regex
+}
+
+/// Converts the bracket expression opening at `start` and returns the index
+/// of its closing `]`, or `None` when there is none and `[` is a literal.
+fn get_bracket_regex(glob: &[char], start: usize) -> Option<(String, usize)> {
+    let mut regex = String::from("[");
+    let mut i = start + 1;
+    if matches!(glob.get(i), Some('!') | Some('^')) {
+        // Like `*` and `?`, a negated expression never matches a separator
+        regex.push_str("^/");
+        i += 1;
+    }
+    // A `]` right after the opening is a member rather than the closing
+    let first = i;
+    loop {
+        let mut c = *glob.get(i)?;
+        if c == ']' && i > first {
+            break;
+        }
+        if c == '\\' && i + 1 < glob.len() {
+            i += 1;
+            c = glob[i];
+            regex.push_str(&regex::escape(&c.to_string()));
+        } else if c == '-' {
+            regex.push(c);
+        } else {
+            regex.push_str(&regex::escape(&c.to_string()));
+        }
+        i += 1;
+    }
+    regex.push(']');
+    Some((regex, i))
+}
+
+fn convert_archive(archive: Archive2) -> Result<Archive, Error> {
+    let start = archive
+        .start
+        .or(archive.time)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing archive start time"))?;
+    Ok(Archive {
+        id: archive.id,
+        name: archive.name,
+        start: parse_time(&start)?,
+        duration: archive.duration,
+        stats: archive.stats,
+    })
+}
+
+fn parse_time(time: &str) -> Result<NaiveDateTime, Error> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Local).naive_local())
+        .or_else(|_| NaiveDateTime::parse_from_str(time, DATETIME_FORMAT))
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))
+}
+
+fn borg_command() -> Command {
+    let mut command = Command::new("borg");
+    command.arg("--log-json");
+    command
+}
+
+async fn status(command: &mut Command) -> Result<(), Error> {
+    let mut child = command.stderr(Stdio::piped()).spawn()?;
+    let errors = read_log(child.stderr.take().unwrap(), None).await?;
+    check_exit_status(child.status().await?, errors)
+}
+
+async fn output(command: &mut Command, progress: Option<&ProgressBar>) -> Result<Vec<u8>, Error> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = child.stdout.take().unwrap();
+    let stdout = task::spawn(async move {
+        let mut buffer = Vec::new();
+        stdout.read_to_end(&mut buffer).await.map(|_| buffer)
+    });
+    let errors = read_log(child.stderr.take().unwrap(), progress).await?;
+    let stdout = stdout.await?;
+    check_exit_status(child.status().await?, errors)?;
+    Ok(stdout)
+}
+
+async fn read_log(
+    stderr: ChildStderr,
+    progress: Option<&ProgressBar>,
+) -> Result<Vec<String>, Error> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut percent: Option<ProgressBar> = None;
+    let mut lines = BufReader::new(stderr).lines();
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        match serde_json::from_str::<LogLine>(&line) {
+            Ok(LogLine::ArchiveProgress {
+                original_size,
+                nfiles,
+                finished,
+            }) => {
+                if let Some(progress) = progress {
+                    if finished {
+                        progress.set_position(progress.length().unwrap_or_default());
+                    }
+                    if let Some(original_size) = original_size {
+                        progress.set_position(original_size);
+                    }
+                    if let Some(nfiles) = nfiles {
+                        progress.set_message(format!("{} files", nfiles));
+                    }
+                }
+            }
+            Ok(LogLine::ProgressPercent {
+                current,
+                total,
+                message,
+                finished,
+            }) => {
+                if finished {
+                    percent = None;
+                    continue;
+                }
+                let percent = percent.get_or_insert_with(|| progress::percent("borg"));
+                if let Some(total) = total {
+                    percent.set_length(total);
+                }
+                if let Some(current) = current {
+                    percent.set_position(current);
+                }
+                if let Some(message) = message {
+                    percent.set_message(message);
+                }
+            }
+            Ok(LogLine::LogMessage {
+                levelname,
+                message,
+                msgid,
+            }) => match levelname.as_str() {
+                "ERROR" | "CRITICAL" => match msgid {
+                    Some(msgid) => errors.push(format!("{} ({})", message, msgid)),
+                    None => errors.push(message),
+                },
+                "DEBUG" => debug!("{}", message),
+                _ => progress::println(&message),
+            },
+            Ok(LogLine::Other) => (),
+            Err(_) => {
+                progress::println(&line);
+                errors.push(line);
+            }
+        }
+    }
+    Ok(errors)
+}
+
+fn check_exit_status(exit_status: ExitStatus, errors: Vec<String>) -> Result<(), Error> {
+    match exit_status.code() {
+        Some(0) => Ok(()),
+        Some(code) if code == 1 || (100..128).contains(&code) => {
+            warn!("borg completed with warnings (exit code {})", code);
+            Ok(())
+        }
+        Some(code) if code >= 128 => Err(Error::new(
+            ErrorKind::Interrupted,
+            format!("borg was killed by signal {}", code - 128),
+        )),
+        Some(code) if errors.is_empty() => {
+            Err(Error::other(format!("borg failed with exit code {}", code)))
+        }
+        Some(_) => Err(Error::other(errors.join("; "))),
+        None => Err(Error::new(
+            ErrorKind::Interrupted,
+            format!(
+                "borg was killed by signal {}",
+                exit_status.signal().unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, path: &str) -> bool {
+        let regex = get_regex(&Pattern::parse(pattern).unwrap());
+        Regex::new(&regex).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn get_patterns_reverses_precedence() {
+        let patterns = vec!["*.log".to_string(), "!keep.log".to_string()];
+        assert_eq!(
+            get_patterns(&patterns),
+            vec![
+                "+ re:^(\\./)?(.*/)?keep\\.log(/|$)",
+                "- re:^(\\./)?(.*/)?[^/]*\\.log(/|$)",
+            ]
+        );
+    }
+
+    #[test]
+    fn get_regex_matches_like_gitignore() {
+        assert!(is_match("*.log", "./a.log"));
+        assert!(is_match("*.log", "src/a.log"));
+        assert!(!is_match("*.log", "a.logs"));
+        assert!(is_match("/target", "./target/debug/a"));
+        assert!(!is_match("/target", "src/target"));
+        assert!(is_match("src/*.rs", "src/main.rs"));
+        assert!(!is_match("src/*.rs", "src/bin/main.rs"));
+        assert!(is_match("a/**/b", "a/b"));
+        assert!(is_match("a/**/b", "a/x/y/b"));
+        assert!(is_match("**/cache", "x/cache/data"));
+        assert!(is_match("out/", "out/a"));
+        assert!(!is_match("out/", "out"));
+        assert!(is_match("file?.[!a-c]", "file1.d"));
+        assert!(!is_match("file?.[!a-c]", "file1.a"));
+        assert!(is_match("b\\[1\\]", "b[1]"));
+        assert!(is_match("a+b", "a+b"));
+        assert!(!is_match("a+b", "aab"));
+    }
+
+    fn exit_code(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn check_exit_status_accepts_warnings() {
+        for code in &[0, 1, 100, 127] {
+            assert!(check_exit_status(exit_code(*code), Vec::new()).is_ok());
+        }
+    }
+
+    #[test]
+    fn check_exit_status_reports_errors() {
+        let error = check_exit_status(exit_code(2), Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "borg failed with exit code 2");
+        let errors = vec![String::from("a"), String::from("b")];
+        let error = check_exit_status(exit_code(99), errors).unwrap_err();
+        assert_eq!(error.to_string(), "a; b");
+    }
+
+    #[test]
+    fn check_exit_status_reports_signals() {
+        let error = check_exit_status(exit_code(130), Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+        assert_eq!(error.to_string(), "borg was killed by signal 2");
+        let error = check_exit_status(ExitStatus::from_raw(9), Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+        assert_eq!(error.to_string(), "borg was killed by signal 9");
+    }
+
+    #[test]
+    fn is_unsupported_version_only_matches_version_errors() {
+        let error = Error::other(
+            "/repo does not have a valid configuration. Check repo config \
+             [repository version 1 is not supported by this borg version]. \
+             (Repository.InvalidRepositoryConfig)",
+        );
+        assert!(is_unsupported_version(&error));
+        let error = Error::other("Unsupported repository version 1");
+        assert!(is_unsupported_version(&error));
+        let error = Error::other("passphrase supplied in BORG_PASSPHRASE is incorrect.");
+        assert!(!is_unsupported_version(&error));
+        let error = Error::other("Repository /repo does not exist. (Repository.DoesNotExist)");
+        assert!(!is_unsupported_version(&error));
+        let error = Error::new(
+            ErrorKind::ConnectionRefused,
+            "Connection closed by remote host",
+        );
+        assert!(!is_unsupported_version(&error));
+    }
+
+    #[test]
+    fn get_regex_converts_bracket_expressions() {
+        assert!(is_match("x[]a]", "x]"));
+        assert!(is_match("x[]a]", "xa"));
+        assert!(!is_match("x[]a]", "xb"));
+        assert!(is_match("x[!]a]", "xb"));
+        assert!(!is_match("x[!]a]", "x]"));
+        assert!(!is_match("x[!]a]", "xa"));
+        assert!(is_match("x[^a]", "xb"));
+        assert!(!is_match("x[^a]", "xa"));
+        assert!(!is_match("x[!a]y", "x/y"));
+        assert!(is_match("x[a-c]", "xb"));
+        assert!(!is_match("x[a-c]", "x-"));
+        assert!(is_match("x[\\]]", "x]"));
+        assert!(is_match("x[&&~]", "x~"));
+        assert!(is_match("x[[]", "x["));
+    }
+
+    #[test]
+    fn get_regex_keeps_unclosed_brackets_literal() {
+        assert!(is_match("x[!]", "x[!]"));
+        assert!(!is_match("x[!]", "xb"));
+        assert!(is_match("x[]", "x[]"));
+        assert!(is_match("x[a", "x[a"));
+    }
+}
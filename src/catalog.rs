@@ -0,0 +1,181 @@
+use super::error::Error;
+use super::model::*;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+#[async_trait]
+pub trait Catalog: Send {
+    async fn create_root_directory(
+        &mut self,
+        path: &str,
+        depth: i16,
+        fingerprint: &str,
+    ) -> Result<(), Error>;
+    async fn update_root_directory(&mut self, id: &Uuid, fingerprint: &str) -> Result<(), Error>;
+    async fn find_root_directories(&mut self) -> Result<Vec<RootDirectory>, Error>;
+    async fn find_root_directory_by_path(
+        &mut self,
+        path: &str,
+    ) -> Result<Option<RootDirectory>, Error>;
+    async fn delete_root_directory(&mut self, id: &Uuid) -> Result<(), Error>;
+    async fn create_directory(
+        &mut self,
+        path: &str,
+        blake3_hash: &str,
+        fingerprint: &str,
+        root_directory_id: &Uuid,
+    ) -> Result<Uuid, Error>;
+    async fn update_directory(
+        &mut self,
+        id: &Uuid,
+        blake3_hash: &str,
+        fingerprint: &str,
+    ) -> Result<(), Error>;
+    async fn update_directory_dirty(&mut self, id: &Uuid, dirty: bool) -> Result<(), Error>;
+    async fn find_directories(&mut self) -> Result<Vec<Directory>, Error>;
+    async fn find_dirty_directories(&mut self) -> Result<Vec<Directory>, Error>;
+    async fn find_directories_without_archives(&mut self) -> Result<Vec<Directory>, Error>;
+    async fn find_directories_with_archives_by_root_directory_id(
+        &mut self,
+        root_directory_id: &Uuid,
+    ) -> Result<Vec<Directory>, Error>;
+    async fn find_directories_without_archives_by_root_directory_id(
+        &mut self,
+        root_directory_id: &Uuid,
+    ) -> Result<Vec<Directory>, Error>;
+    async fn find_directory_by_id(&mut self, id: &Uuid) -> Result<Directory, Error>;
+    async fn find_directory_by_path(&mut self, path: &str) -> Result<Option<Directory>, Error>;
+    async fn delete_directory(&mut self, id: &Uuid) -> Result<(), Error>;
+    async fn create_archive(&mut self, archive: &Archive) -> Result<(), Error>;
+    async fn find_archive_by_id(&mut self, id: &Uuid) -> Result<Archive, Error>;
+    async fn find_archives_by_repo_id(&mut self, repo_id: &str) -> Result<Vec<Archive>, Error>;
+    async fn find_archives_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<Vec<Archive>, Error>;
+    async fn find_latest_archive_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<Option<Archive>, Error>;
+    async fn find_latest_archive_by_directory_id_and_repo_id(
+        &mut self,
+        directory_id: &Uuid,
+        repo_id: &str,
+    ) -> Result<Option<Archive>, Error>;
+    async fn find_repo_ids_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<Vec<String>, Error>;
+    async fn find_archive_by_repo_id_and_archive_id(
+        &mut self,
+        repo_id: &str,
+        archive_id: &str,
+    ) -> Result<Option<Archive>, Error>;
+    async fn delete_archive(&mut self, id: &Uuid) -> Result<(), Error>;
+    async fn create_archive_stats(&mut self, archive_stats: &ArchiveStats) -> Result<(), Error>;
+    async fn find_archive_stats_by_archive_id(
+        &mut self,
+        archive_id: &Uuid,
+    ) -> Result<Option<ArchiveStats>, Error>;
+    async fn create_retention_policy(
+        &mut self,
+        retention_policy: &RetentionPolicy,
+    ) -> Result<(), Error>;
+    async fn find_retention_policy_by_scope(
+        &mut self,
+        root_directory_id: Option<&Uuid>,
+        directory_id: Option<&Uuid>,
+    ) -> Result<Option<RetentionPolicy>, Error>;
+    async fn delete_retention_policy(&mut self, id: &Uuid) -> Result<(), Error>;
+    async fn create_repository(
+        &mut self,
+        label: &str,
+        location: &str,
+        repo_id: &str,
+        encryption: &str,
+        last_seen_date: &NaiveDateTime,
+    ) -> Result<(), Error>;
+    async fn update_repository_last_seen_date(
+        &mut self,
+        id: &Uuid,
+        last_seen_date: &NaiveDateTime,
+    ) -> Result<(), Error>;
+    async fn find_repositories(&mut self) -> Result<Vec<Repository>, Error>;
+    async fn find_repository_by_label(&mut self, label: &str) -> Result<Option<Repository>, Error>;
+    async fn find_repository_by_repo_id(
+        &mut self,
+        repo_id: &str,
+    ) -> Result<Option<Repository>, Error>;
+    async fn delete_repository(&mut self, id: &Uuid) -> Result<(), Error>;
+    async fn count_archives_by_repo_id(&mut self, repo_id: &str) -> Result<i64, Error>;
+    async fn update_repository_drive_id(&mut self, id: &Uuid, drive_id: &Uuid)
+        -> Result<(), Error>;
+    async fn find_repositories_by_drive_id(
+        &mut self,
+        drive_id: &Uuid,
+    ) -> Result<Vec<Repository>, Error>;
+    async fn create_drive(
+        &mut self,
+        uuid: &str,
+        label: Option<&str>,
+        size: i64,
+        avail: i64,
+        last_mounted_date: &NaiveDateTime,
+    ) -> Result<Uuid, Error>;
+    async fn update_drive(
+        &mut self,
+        id: &Uuid,
+        label: Option<&str>,
+        size: i64,
+        avail: i64,
+        last_mounted_date: &NaiveDateTime,
+    ) -> Result<(), Error>;
+    async fn find_drives(&mut self) -> Result<Vec<Drive>, Error>;
+    async fn find_drive_by_id(&mut self, id: &Uuid) -> Result<Drive, Error>;
+    async fn find_drive_by_uuid(&mut self, uuid: &str) -> Result<Option<Drive>, Error>;
+    async fn create_replication_policy(
+        &mut self,
+        replication_policy: &ReplicationPolicy,
+    ) -> Result<(), Error>;
+    async fn find_replication_policy_by_scope(
+        &mut self,
+        root_directory_id: Option<&Uuid>,
+        directory_id: Option<&Uuid>,
+    ) -> Result<Option<ReplicationPolicy>, Error>;
+    async fn delete_replication_policy(&mut self, id: &Uuid) -> Result<(), Error>;
+    async fn create_exclusion_policy(
+        &mut self,
+        exclusion_policy: &ExclusionPolicy,
+    ) -> Result<(), Error>;
+    async fn find_exclusion_policy_by_scope(
+        &mut self,
+        root_directory_id: Option<&Uuid>,
+        directory_id: Option<&Uuid>,
+    ) -> Result<Option<ExclusionPolicy>, Error>;
+    async fn delete_exclusion_policy(&mut self, id: &Uuid) -> Result<(), Error>;
+    async fn create_manifest_entries(
+        &mut self,
+        directory_id: &Uuid,
+        manifest_entries: &[ManifestEntry],
+    ) -> Result<(), Error>;
+    async fn copy_manifest_entries(
+        &mut self,
+        directory_id: &Uuid,
+        source_archive_id: Option<&Uuid>,
+        archive_id: &Uuid,
+    ) -> Result<(), Error>;
+    async fn find_manifest_entries_by_directory_id_and_archive_id(
+        &mut self,
+        directory_id: &Uuid,
+        archive_id: Option<&Uuid>,
+    ) -> Result<Vec<ManifestEntry>, Error>;
+    async fn exists_manifest_entries_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<bool, Error>;
+    async fn delete_manifest_entries_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<(), Error>;
+}
@@ -1,8 +1,13 @@
 use std::str::FromStr;
 
+use super::archiver;
+use super::catalog::Catalog;
+use super::config::Config;
+use super::error::{Error, Failures};
+use super::fingerprint;
+use super::model::{ExclusionPolicy, ReplicationPolicy, RetentionPolicy};
 use super::permafrust;
 use clap::{App, Arg, ArgMatches, SubCommand};
-use sqlx::PgPool;
 use uuid::Uuid;
 
 pub fn watch_subcommand<'a, 'b>() -> App<'a, 'b> {
@@ -18,6 +23,16 @@ pub fn watch_subcommand<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .default_value("0"),
         )
+        .arg(
+            Arg::with_name("FINGERPRINT")
+                .short("f")
+                .long("fingerprint")
+                .help("Fingerprint directories by metadata or by content")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&fingerprint::FINGERPRINTS)
+                .default_value(fingerprint::METADATA),
+        )
 }
 
 pub fn unwatch_subcommand<'a, 'b>() -> App<'a, 'b> {
@@ -32,7 +47,42 @@ pub fn unwatch_subcommand<'a, 'b>() -> App<'a, 'b> {
 }
 
 pub fn scan_subcommand<'a, 'b>() -> App<'a, 'b> {
-    SubCommand::with_name("scan").about("Scan all root directories")
+    SubCommand::with_name("scan")
+        .about("Scan all root directories")
+        .arg(
+            Arg::with_name("INCREMENTAL")
+                .short("i")
+                .long("incremental")
+                .help("Only rehash files whose size, mtime or inode changed")
+                .required(false),
+        )
+}
+
+pub fn status_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("status").about("Show the backup status of all directories")
+}
+
+pub fn daemon_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("daemon")
+        .about("Watch root directories for changes and rehash dirty directories")
+        .arg(
+            Arg::with_name("DEBOUNCE")
+                .short("b")
+                .long("debounce")
+                .help("Seconds without events before rehashing dirty directories")
+                .required(false)
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .arg(
+            Arg::with_name("INTERVAL")
+                .short("t")
+                .long("interval")
+                .help("Maximum seconds between two rehashes")
+                .required(false)
+                .takes_value(true)
+                .default_value("300"),
+        )
 }
 
 pub fn init_subcommand<'a, 'b>() -> App<'a, 'b> {
@@ -42,7 +92,7 @@ pub fn init_subcommand<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("REPO")
                 .short("r")
                 .long("repo")
-                .help("Borg repo")
+                .help("Borg repo location, label or configured name")
                 .required(false)
                 .env("BORG_REPO"),
         )
@@ -57,14 +107,24 @@ pub fn init_subcommand<'a, 'b>() -> App<'a, 'b> {
 }
 
 pub fn list_subcommand<'a, 'b>() -> App<'a, 'b> {
-    SubCommand::with_name("list").about("List archives").arg(
-        Arg::with_name("REPO")
-            .short("r")
-            .long("repo")
-            .help("Borg repo")
-            .required(false)
-            .env("BORG_REPO"),
-    )
+    SubCommand::with_name("list")
+        .about("List archives")
+        .arg(
+            Arg::with_name("REPO")
+                .short("r")
+                .long("repo")
+                .help("Borg repo location, label or configured name")
+                .required(false)
+                .env("BORG_REPO"),
+        )
+        .arg(
+            Arg::with_name("DIRECTORY")
+                .short("d")
+                .long("directory")
+                .help("List all archive generations of the directory with this UUID")
+                .required(false)
+                .takes_value(true),
+        )
 }
 
 pub fn create_subcommand<'a, 'b>() -> App<'a, 'b> {
@@ -80,7 +140,7 @@ pub fn create_subcommand<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("REPO")
                 .short("r")
                 .long("repo")
-                .help("Borg repo")
+                .help("Borg repo location, label or configured name")
                 .required(false)
                 .env("BORG_REPO"),
         )
@@ -112,7 +172,7 @@ pub fn update_subcommand<'a, 'b>() -> App<'a, 'b> {
         .about("Update archives")
         .arg(
             Arg::with_name("UUIDS")
-                .help("UUIDs of archives to update")
+                .help("UUIDs of directories to update")
                 .required(true)
                 .multiple(true),
         )
@@ -120,7 +180,7 @@ pub fn update_subcommand<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("REPO")
                 .short("r")
                 .long("repo")
-                .help("Borg repo")
+                .help("Borg repo location, label or configured name")
                 .required(false)
                 .env("BORG_REPO"),
         )
@@ -147,6 +207,246 @@ pub fn update_subcommand<'a, 'b>() -> App<'a, 'b> {
         )
 }
 
+pub fn prune_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("prune")
+        .about("Prune archives according to their retention policy")
+        .arg(
+            Arg::with_name("UUIDS")
+                .help("UUIDs of directories to prune")
+                .required(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("REPO")
+                .short("r")
+                .long("repo")
+                .help("Borg repo location, label or configured name")
+                .required(false)
+                .env("BORG_REPO"),
+        )
+        .arg(
+            Arg::with_name("DRYRUN")
+                .short("n")
+                .long("dry-run")
+                .help("Dry run")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("ROOTDIRS")
+                .long("root-directories")
+                .help("Use root directories UUIDs")
+                .required(false),
+        )
+}
+
+pub fn diff_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("diff")
+        .about("Show files changed since a directory was archived")
+        .arg(
+            Arg::with_name("UUID")
+                .help("UUID of the directory")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("ARCHIVE")
+                .short("a")
+                .long("archive")
+                .help("UUID of the archive to compare with, defaults to the latest")
+                .required(false)
+                .takes_value(true),
+        )
+}
+
+pub fn retention_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("retention")
+        .about("Show or set retention policies, globally or for a (root) directory")
+        .arg(
+            Arg::with_name("ROOTDIR")
+                .long("root-directory")
+                .help("UUID of the root directory")
+                .required(false)
+                .takes_value(true)
+                .conflicts_with("DIRECTORY"),
+        )
+        .arg(
+            Arg::with_name("DIRECTORY")
+                .long("directory")
+                .help("UUID of the directory")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("KEEPWITHIN")
+                .long("keep-within")
+                .help("Keep all archives within this time interval")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("KEEPLAST")
+                .long("keep-last")
+                .help("Number of archives to keep")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("KEEPDAILY")
+                .long("keep-daily")
+                .help("Number of daily archives to keep")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("KEEPWEEKLY")
+                .long("keep-weekly")
+                .help("Number of weekly archives to keep")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("KEEPMONTHLY")
+                .long("keep-monthly")
+                .help("Number of monthly archives to keep")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("KEEPYEARLY")
+                .long("keep-yearly")
+                .help("Number of yearly archives to keep")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("UNSET")
+                .long("unset")
+                .help("Unset the retention policy")
+                .required(false)
+                .conflicts_with_all(&[
+                    "KEEPWITHIN",
+                    "KEEPLAST",
+                    "KEEPDAILY",
+                    "KEEPWEEKLY",
+                    "KEEPMONTHLY",
+                    "KEEPYEARLY",
+                ]),
+        )
+}
+
+pub fn replicate_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("replicate")
+        .about("Replicate directories to another repository")
+        .arg(
+            Arg::with_name("UUIDS")
+                .help("UUIDs of directories to replicate")
+                .required(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("REPO")
+                .short("r")
+                .long("repo")
+                .help("Target borg repo location, label or configured name")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("COMPRESSION")
+                .short("c")
+                .long("compression")
+                .help("Borg compression")
+                .required(false)
+                .env("BORG_COMPRESSION"),
+        )
+        .arg(
+            Arg::with_name("TRANSFER")
+                .short("t")
+                .long("transfer")
+                .help("Transfer the latest archive instead of archiving the source directory")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("DRYRUN")
+                .short("n")
+                .long("dry-run")
+                .help("Dry run")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("ROOTDIRS")
+                .long("root-directories")
+                .help("Use root directories UUIDs")
+                .required(false),
+        )
+}
+
+pub fn replication_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("replication")
+        .about("Show or set replication policies, globally or for a (root) directory")
+        .arg(
+            Arg::with_name("ROOTDIR")
+                .long("root-directory")
+                .help("UUID of the root directory")
+                .required(false)
+                .takes_value(true)
+                .conflicts_with("DIRECTORY"),
+        )
+        .arg(
+            Arg::with_name("DIRECTORY")
+                .long("directory")
+                .help("UUID of the directory")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("COPIES")
+                .long("copies")
+                .help("Number of repositories each directory must be archived in")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("UNSET")
+                .long("unset")
+                .help("Unset the replication policy")
+                .required(false)
+                .conflicts_with("COPIES"),
+        )
+}
+
+pub fn exclude_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("exclude")
+        .about("Show or set exclusion patterns, globally or for a (root) directory")
+        .arg(
+            Arg::with_name("ROOTDIR")
+                .long("root-directory")
+                .help("UUID of the root directory")
+                .required(false)
+                .takes_value(true)
+                .conflicts_with("DIRECTORY"),
+        )
+        .arg(
+            Arg::with_name("DIRECTORY")
+                .long("directory")
+                .help("UUID of the directory")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("PATTERNS")
+                .help("Gitignore-style patterns, prefix with ! to include")
+                .required(false)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("UNSET")
+                .long("unset")
+                .help("Unset the exclusion patterns")
+                .required(false)
+                .conflicts_with("PATTERNS"),
+        )
+}
+
 pub fn delete_subcommand<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("delete")
         .about("Delete archives")
@@ -160,7 +460,7 @@ pub fn delete_subcommand<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("REPO")
                 .short("r")
                 .long("repo")
-                .help("Borg repo")
+                .help("Borg repo location, label or configured name")
                 .required(false)
                 .env("BORG_REPO"),
         )
@@ -186,7 +486,7 @@ pub fn extract_subcommand<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("REPO")
                 .short("r")
                 .long("repo")
-                .help("Borg repo")
+                .help("Borg repo location, label or configured name")
                 .required(false)
                 .env("BORG_REPO"),
         )
@@ -212,7 +512,7 @@ pub fn check_subcommand<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("REPO")
                 .short("r")
                 .long("repo")
-                .help("Borg repo")
+                .help("Borg repo location, label or configured name")
                 .required(false)
                 .env("BORG_REPO"),
         )
@@ -224,132 +524,835 @@ pub fn check_subcommand<'a, 'b>() -> App<'a, 'b> {
         )
 }
 
-pub async fn watch(pool: &PgPool, matches: &ArgMatches<'_>) {
+pub fn transfer_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("transfer")
+        .about("Transfer archives from a borg 1.x repository to a borg 2 repository")
+        .arg(
+            Arg::with_name("REPO")
+                .short("r")
+                .long("repo")
+                .help("Borg repo location, label or configured name")
+                .required(false)
+                .env("BORG_REPO"),
+        )
+        .arg(
+            Arg::with_name("OTHERREPO")
+                .long("other-repo")
+                .help("Label or location of the repository to transfer archives from")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("DRYRUN")
+                .short("n")
+                .long("dry-run")
+                .help("Dry run")
+                .required(false),
+        )
+}
+
+pub fn reconcile_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("reconcile")
+        .about("Compare the catalog with the archives of a repository")
+        .arg(
+            Arg::with_name("REPO")
+                .short("r")
+                .long("repo")
+                .help("Borg repo location, label or configured name")
+                .required(false)
+                .env("BORG_REPO"),
+        )
+        .arg(
+            Arg::with_name("FIX")
+                .long("fix")
+                .help("Remove missing archives from the catalog and adopt orphaned ones")
+                .required(false),
+        )
+}
+
+pub fn import_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("import")
+        .about("Import archives created outside permafrust into the catalog")
+        .arg(
+            Arg::with_name("REPO")
+                .short("r")
+                .long("repo")
+                .help("Borg repo location, label or configured name")
+                .required(false)
+                .env("BORG_REPO"),
+        )
+        .arg(
+            Arg::with_name("MAPPING")
+                .short("m")
+                .long("mapping")
+                .help("File mapping archive name prefixes to directory paths, one per line")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("DRYRUN")
+                .short("n")
+                .long("dry-run")
+                .help("Dry run")
+                .required(false),
+        )
+}
+
+pub fn plan_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("plan")
+        .about("Plan the archiving of all directories not backed up across repositories")
+        .arg(
+            Arg::with_name("COMPRESSION")
+                .short("c")
+                .long("compression")
+                .help("Borg compression")
+                .required(false)
+                .env("BORG_COMPRESSION"),
+        )
+        .arg(
+            Arg::with_name("EXECUTE")
+                .short("x")
+                .long("execute")
+                .help("Archive directories according to the plan")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("DRYRUN")
+                .short("n")
+                .long("dry-run")
+                .help("Dry run")
+                .required(false),
+        )
+}
+
+pub fn repo_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("repo")
+        .about("Manage borg repositories")
+        .subcommands(vec![
+            SubCommand::with_name("add")
+                .about("Add a borg repository")
+                .arg(
+                    Arg::with_name("LOCATION")
+                        .help("Borg repo location")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("LABEL")
+                        .help("Borg repo label")
+                        .required(true),
+                ),
+            SubCommand::with_name("list").about("List borg repositories"),
+            SubCommand::with_name("remove")
+                .about("Remove borg repositories")
+                .arg(
+                    Arg::with_name("LABELS")
+                        .help("Labels of borg repos to remove")
+                        .required(true)
+                        .multiple(true),
+                ),
+        ])
+}
+
+pub fn drive_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("drive")
+        .about("Manage drives holding borg repositories")
+        .subcommands(vec![
+            SubCommand::with_name("list").about("Refresh mounted drives and list all drives")
+        ])
+}
+
+pub fn where_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("where")
+        .about("Show which repositories and drives hold a directory's archives")
+        .arg(
+            Arg::with_name("UUIDS")
+                .help("UUIDs of directories to locate")
+                .required(true)
+                .multiple(true),
+        )
+}
+
+pub fn stats_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("stats")
+        .about("Show archive statistics per repository or directory")
+        .arg(
+            Arg::with_name("REPO")
+                .short("r")
+                .long("repo")
+                .help("Label of the repository to show archive by archive")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("DIRECTORY")
+                .short("d")
+                .long("directory")
+                .help("Show the archive history of the directory with this UUID")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("REFRESH")
+                .long("refresh")
+                .help("Fetch missing statistics from mounted repositories first")
+                .required(false),
+        )
+}
+
+pub async fn watch(
+    catalog: &mut dyn Catalog,
+    _config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
     permafrust::watch(
-        &mut pool.acquire().await.unwrap(),
+        catalog,
         matches.value_of("PATH").unwrap(),
-        i16::from_str_radix(matches.value_of("DEPTH").unwrap(), 10)
-            .expect("Depth is not a valid number"),
+        parse_number(matches.value_of("DEPTH").unwrap(), "Depth")?,
+        matches.value_of("FINGERPRINT").unwrap(),
     )
-    .await;
+    .await
 }
 
-pub async fn unwatch(pool: &PgPool, matches: &ArgMatches<'_>) {
+pub async fn unwatch(
+    catalog: &mut dyn Catalog,
+    _config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
     for path in matches.values_of("PATHS").unwrap() {
-        permafrust::unwatch(&mut pool.acquire().await.unwrap(), path).await;
+        permafrust::unwatch(catalog, path).await?;
     }
+    Ok(())
+}
+
+pub async fn scan(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    permafrust::scan(catalog, config, matches.is_present("INCREMENTAL")).await
 }
 
-pub async fn scan(pool: &PgPool, _: &ArgMatches<'_>) {
-    permafrust::scan(&mut pool.acquire().await.unwrap()).await;
+pub async fn status(catalog: &mut dyn Catalog, _config: &Config) -> Result<(), Error> {
+    permafrust::status(catalog).await
 }
 
-pub async fn init(matches: &ArgMatches<'_>) {
-    permafrust::init(
-        &matches.value_of("REPO").unwrap(),
-        &matches.value_of("ENCRYPTION").unwrap(),
+pub async fn daemon(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    permafrust::daemon(
+        catalog,
+        config,
+        parse_number(matches.value_of("DEBOUNCE").unwrap(), "Debounce")?,
+        parse_number(matches.value_of("INTERVAL").unwrap(), "Interval")?,
     )
-    .await;
+    .await
+}
+
+pub async fn init(config: &Config, matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let repo = get_repo_name(config, matches)?;
+    let encryption = matches
+        .value_of("ENCRYPTION")
+        .or_else(|| config.encryption(repo))
+        .ok_or_else(|| Error::Config(String::from("No encryption specified")))?;
+    permafrust::init(archiver.as_ref(), config.repo_location(repo), encryption).await
 }
 
-pub async fn list(pool: &PgPool, matches: &ArgMatches<'_>) {
+pub async fn list(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let directory_id = matches.value_of("DIRECTORY").map(parse_uuid).transpose()?;
     permafrust::list(
-        &mut pool.acquire().await.unwrap(),
-        &matches.value_of("REPO").unwrap(),
+        catalog,
+        archiver.as_ref(),
+        get_optional_repo(config, matches).as_deref(),
+        directory_id.as_ref(),
     )
-    .await;
+    .await
 }
 
-pub async fn create(pool: &PgPool, matches: &ArgMatches<'_>) {
-    let uuids: Vec<Uuid> = matches
-        .values_of("UUIDS")
-        .unwrap()
-        .into_iter()
-        .map(|s| Uuid::from_str(s).unwrap())
-        .collect();
-    for uuid in &uuids {
-        permafrust::create(
-            &mut pool.acquire().await.unwrap(),
-            matches.value_of("REPO").unwrap(),
+pub async fn create(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let config = &get_config(config, matches)?;
+    let mut failures = Failures::new();
+    for uuid in &get_uuids(matches)? {
+        match permafrust::create(
+            catalog,
+            archiver.as_ref(),
+            &get_repo(config, matches)?,
             uuid,
-            matches.value_of("COMPRESSION").unwrap(),
+            config,
             matches.is_present("DRYRUN"),
             matches.is_present("ROOTDIRS"),
         )
-        .await;
+        .await
+        {
+            Ok(mut directory_failures) => failures.append(&mut directory_failures),
+            Err(error) => failures.push((uuid.to_string(), error)),
+        }
     }
+    report_failures(failures)
 }
 
-pub async fn update(pool: &PgPool, matches: &ArgMatches<'_>) {
-    let uuids: Vec<Uuid> = matches
-        .values_of("UUIDS")
-        .unwrap()
-        .into_iter()
-        .map(|s| Uuid::from_str(s).unwrap())
-        .collect();
-    for uuid in &uuids {
-        permafrust::update(
-            &mut pool.acquire().await.unwrap(),
-            matches.value_of("REPO").unwrap(),
+pub async fn update(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let config = &get_config(config, matches)?;
+    let mut failures = Failures::new();
+    for uuid in &get_uuids(matches)? {
+        match permafrust::update(
+            catalog,
+            archiver.as_ref(),
+            get_optional_repo(config, matches).as_deref(),
             uuid,
-            matches.value_of("COMPRESSION").unwrap(),
+            config,
             matches.is_present("DRYRUN"),
             matches.is_present("ROOTDIRS"),
         )
-        .await;
+        .await
+        {
+            Ok(mut directory_failures) => failures.append(&mut directory_failures),
+            Err(error) => failures.push((uuid.to_string(), error)),
+        }
     }
+    report_failures(failures)
 }
 
-pub async fn delete(pool: &PgPool, matches: &ArgMatches<'_>) {
-    let uuids: Vec<Uuid> = matches
-        .values_of("UUIDS")
-        .unwrap()
-        .into_iter()
-        .map(|s| Uuid::from_str(s).unwrap())
-        .collect();
-    for uuid in &uuids {
-        permafrust::delete(
-            &mut pool.acquire().await.unwrap(),
-            matches.value_of("REPO").unwrap(),
+pub async fn prune(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let mut failures = Failures::new();
+    for uuid in &get_uuids(matches)? {
+        match permafrust::prune(
+            catalog,
+            archiver.as_ref(),
+            get_optional_repo(config, matches).as_deref(),
             uuid,
+            config,
             matches.is_present("DRYRUN"),
+            matches.is_present("ROOTDIRS"),
         )
-        .await;
+        .await
+        {
+            Ok(mut directory_failures) => failures.append(&mut directory_failures),
+            Err(error) => failures.push((uuid.to_string(), error)),
+        }
     }
+    report_failures(failures)
 }
 
-pub async fn extract(pool: &PgPool, matches: &ArgMatches<'_>) {
-    let uuids: Vec<Uuid> = matches
-        .values_of("UUIDS")
-        .unwrap()
-        .into_iter()
-        .map(|s| Uuid::from_str(s).unwrap())
-        .collect();
-    for uuid in &uuids {
-        permafrust::extract(
-            &mut pool.acquire().await.unwrap(),
-            matches.value_of("REPO").unwrap(),
+pub async fn diff(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archive_id = matches.value_of("ARCHIVE").map(parse_uuid).transpose()?;
+    permafrust::diff(
+        catalog,
+        config,
+        &parse_uuid(matches.value_of("UUID").unwrap())?,
+        archive_id.as_ref(),
+    )
+    .await
+}
+
+pub async fn retention(
+    catalog: &mut dyn Catalog,
+    _config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let root_directory_id = matches.value_of("ROOTDIR").map(parse_uuid).transpose()?;
+    let directory_id = matches.value_of("DIRECTORY").map(parse_uuid).transpose()?;
+    let keep = |name: &str| {
+        matches
+            .value_of(name)
+            .map(|s| parse_number(s, "Keep value"))
+            .transpose()
+    };
+    let retention_policy = RetentionPolicy {
+        id: Uuid::new_v4(),
+        root_directory_id,
+        directory_id,
+        keep_within: matches.value_of("KEEPWITHIN").map(String::from),
+        keep_last: keep("KEEPLAST")?,
+        keep_daily: keep("KEEPDAILY")?,
+        keep_weekly: keep("KEEPWEEKLY")?,
+        keep_monthly: keep("KEEPMONTHLY")?,
+        keep_yearly: keep("KEEPYEARLY")?,
+    };
+    if matches.is_present("UNSET") {
+        permafrust::unset_retention_policy(
+            catalog,
+            root_directory_id.as_ref(),
+            directory_id.as_ref(),
+        )
+        .await
+    } else if retention_policy.keep_within.is_some()
+        || retention_policy.keep_last.is_some()
+        || retention_policy.keep_daily.is_some()
+        || retention_policy.keep_weekly.is_some()
+        || retention_policy.keep_monthly.is_some()
+        || retention_policy.keep_yearly.is_some()
+    {
+        permafrust::set_retention_policy(catalog, &retention_policy).await
+    } else {
+        permafrust::show_retention_policy(
+            catalog,
+            root_directory_id.as_ref(),
+            directory_id.as_ref(),
+        )
+        .await
+    }
+}
+
+pub async fn replicate(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let config = &get_config(config, matches)?;
+    let mut failures = Failures::new();
+    for uuid in &get_uuids(matches)? {
+        match permafrust::replicate(
+            catalog,
+            archiver.as_ref(),
+            &get_repo(config, matches)?,
             uuid,
+            config,
+            matches.is_present("TRANSFER"),
             matches.is_present("DRYRUN"),
+            matches.is_present("ROOTDIRS"),
         )
-        .await;
+        .await
+        {
+            Ok(mut directory_failures) => failures.append(&mut directory_failures),
+            Err(error) => failures.push((uuid.to_string(), error)),
+        }
     }
+    report_failures(failures)
 }
 
-pub async fn check(pool: &PgPool, matches: &ArgMatches<'_>) {
-    let uuids: Vec<Uuid> = matches
-        .values_of("UUIDS")
-        .unwrap()
-        .into_iter()
-        .map(|s| Uuid::from_str(s).unwrap())
-        .collect();
-    for uuid in &uuids {
-        permafrust::check(
-            &mut pool.acquire().await.unwrap(),
-            matches.value_of("REPO").unwrap(),
+pub async fn replication(
+    catalog: &mut dyn Catalog,
+    _config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let root_directory_id = matches.value_of("ROOTDIR").map(parse_uuid).transpose()?;
+    let directory_id = matches.value_of("DIRECTORY").map(parse_uuid).transpose()?;
+    if matches.is_present("UNSET") {
+        permafrust::unset_replication_policy(
+            catalog,
+            root_directory_id.as_ref(),
+            directory_id.as_ref(),
+        )
+        .await
+    } else if let Some(copies) = matches.value_of("COPIES") {
+        let replication_policy = ReplicationPolicy {
+            id: Uuid::new_v4(),
+            root_directory_id,
+            directory_id,
+            copies: parse_number(copies, "Copies")?,
+        };
+        permafrust::set_replication_policy(catalog, &replication_policy).await
+    } else {
+        permafrust::show_replication_policy(
+            catalog,
+            root_directory_id.as_ref(),
+            directory_id.as_ref(),
+        )
+        .await
+    }
+}
+
+pub async fn exclude(
+    catalog: &mut dyn Catalog,
+    _config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let root_directory_id = matches.value_of("ROOTDIR").map(parse_uuid).transpose()?;
+    let directory_id = matches.value_of("DIRECTORY").map(parse_uuid).transpose()?;
+    if matches.is_present("UNSET") {
+        permafrust::unset_exclusion_policy(
+            catalog,
+            root_directory_id.as_ref(),
+            directory_id.as_ref(),
+        )
+        .await
+    } else if let Some(patterns) = matches.values_of("PATTERNS") {
+        let exclusion_policy = ExclusionPolicy {
+            id: Uuid::new_v4(),
+            root_directory_id,
+            directory_id,
+            patterns: patterns.collect::<Vec<&str>>().join("\n"),
+        };
+        permafrust::set_exclusion_policy(catalog, &exclusion_policy).await
+    } else {
+        permafrust::show_exclusion_policy(
+            catalog,
+            root_directory_id.as_ref(),
+            directory_id.as_ref(),
+        )
+        .await
+    }
+}
+
+pub async fn delete(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let mut failures = Failures::new();
+    for uuid in &get_uuids(matches)? {
+        if let Err(error) = permafrust::delete(
+            catalog,
+            archiver.as_ref(),
+            get_optional_repo(config, matches).as_deref(),
+            uuid,
+            matches.is_present("DRYRUN"),
+        )
+        .await
+        {
+            failures.push((uuid.to_string(), error));
+        }
+    }
+    report_failures(failures)
+}
+
+pub async fn extract(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let mut failures = Failures::new();
+    for uuid in &get_uuids(matches)? {
+        if let Err(error) = permafrust::extract(
+            catalog,
+            archiver.as_ref(),
+            get_optional_repo(config, matches).as_deref(),
+            uuid,
+            matches.is_present("DRYRUN"),
+        )
+        .await
+        {
+            failures.push((uuid.to_string(), error));
+        }
+    }
+    report_failures(failures)
+}
+
+pub async fn check(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let mut failures = Failures::new();
+    for uuid in &get_uuids(matches)? {
+        if let Err(error) = permafrust::check(
+            catalog,
+            archiver.as_ref(),
+            get_optional_repo(config, matches).as_deref(),
             uuid,
             matches.is_present("REPAIR"),
         )
-        .await;
+        .await
+        {
+            failures.push((uuid.to_string(), error));
+        }
+    }
+    report_failures(failures)
+}
+
+pub async fn transfer(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    permafrust::transfer(
+        catalog,
+        archiver.as_ref(),
+        &get_repo(config, matches)?,
+        config.repo_location(matches.value_of("OTHERREPO").unwrap()),
+        matches.is_present("DRYRUN"),
+    )
+    .await
+}
+
+pub async fn reconcile(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    permafrust::reconcile(
+        catalog,
+        archiver.as_ref(),
+        &get_repo(config, matches)?,
+        matches.is_present("FIX"),
+    )
+    .await
+}
+
+pub async fn import(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    permafrust::import(
+        catalog,
+        archiver.as_ref(),
+        &get_repo(config, matches)?,
+        matches.value_of("MAPPING"),
+        matches.is_present("DRYRUN"),
+    )
+    .await
+}
+
+pub async fn plan(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let failures = permafrust::plan(
+        catalog,
+        archiver.as_ref(),
+        &get_config(config, matches)?,
+        matches.is_present("EXECUTE"),
+        matches.is_present("DRYRUN"),
+    )
+    .await?;
+    report_failures(failures)
+}
+
+pub async fn repo(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    match matches.subcommand() {
+        ("add", Some(matches)) => {
+            permafrust::add_repository(
+                catalog,
+                archiver.as_ref(),
+                matches.value_of("LOCATION").unwrap(),
+                matches.value_of("LABEL").unwrap(),
+            )
+            .await
+        }
+        ("list", Some(_)) => permafrust::list_repositories(catalog).await,
+        ("remove", Some(matches)) => {
+            let mut failures = Failures::new();
+            for label in matches.values_of("LABELS").unwrap() {
+                if let Err(error) = permafrust::remove_repository(catalog, label).await {
+                    failures.push((label.to_string(), error));
+                }
+            }
+            report_failures(failures)
+        }
+        _ => Ok(()),
+    }
+}
+
+pub async fn drive(
+    catalog: &mut dyn Catalog,
+    _config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("list", Some(_)) => permafrust::list_drives(catalog).await,
+        _ => Ok(()),
+    }
+}
+
+pub async fn locate(
+    catalog: &mut dyn Catalog,
+    _config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    for uuid in &get_uuids(matches)? {
+        permafrust::locate(catalog, uuid).await?;
+    }
+    Ok(())
+}
+
+pub async fn stats(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Error> {
+    let archiver = get_archiver(config, matches)?;
+    let directory_id = matches.value_of("DIRECTORY").map(parse_uuid).transpose()?;
+    permafrust::stats(
+        catalog,
+        archiver.as_ref(),
+        get_optional_repo(config, matches).as_deref(),
+        directory_id.as_ref(),
+        matches.is_present("REFRESH"),
+    )
+    .await
+}
+
+fn report_failures(mut failures: Failures) -> Result<(), Error> {
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0).1),
+        count => {
+            eprintln!("{} failure(s):", count);
+            for (target, error) in &failures {
+                eprintln!("    {}: {}", target, error);
+            }
+            Err(Error::Batch(count))
+        }
+    }
+}
+
+fn get_uuids(matches: &ArgMatches) -> Result<Vec<Uuid>, Error> {
+    matches
+        .values_of("UUIDS")
+        .unwrap()
+        .map(parse_uuid)
+        .collect()
+}
+
+fn parse_uuid(uuid: &str) -> Result<Uuid, Error> {
+    Uuid::from_str(uuid).map_err(|_| Error::Config(format!("{} is not a valid UUID", uuid)))
+}
+
+fn parse_number<T: FromStr>(value: &str, name: &str) -> Result<T, Error> {
+    value
+        .parse::<T>()
+        .map_err(|_| Error::Config(format!("{} is not a valid number", name)))
+}
+
+fn get_config(config: &Config, matches: &ArgMatches) -> Result<Config, Error> {
+    let mut config = config.clone();
+    if let Some(compression) = matches.value_of("COMPRESSION") {
+        config.compression = Some(compression.to_string());
+        // BORG_COMPRESSION only replaces the default, see Config
+        if matches.occurrences_of("COMPRESSION") > 0 {
+            for root in config.roots.values_mut() {
+                root.compression = None;
+            }
+        }
+    }
+    if let Some(margin) = matches.value_of("MARGIN") {
+        config.margin = parse_number(margin, "Margin")?;
+    }
+    if matches.is_present("ESTIMATE_DRY_RUN") {
+        config.estimate_dry_run = true;
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+fn get_repo_name<'a>(config: &'a Config, matches: &'a ArgMatches) -> Result<&'a str, Error> {
+    matches
+        .value_of("REPO")
+        .or(config.repo.as_deref())
+        .ok_or_else(|| Error::Config(String::from("No repository specified")))
+}
+
+fn get_repo(config: &Config, matches: &ArgMatches) -> Result<String, Error> {
+    get_repo_name(config, matches).map(|repo| config.repo_location(repo).to_string())
+}
+
+fn get_optional_repo(config: &Config, matches: &ArgMatches) -> Option<String> {
+    get_repo(config, matches).ok()
+}
+
+fn get_archiver(
+    config: &Config,
+    matches: &ArgMatches,
+) -> Result<Box<dyn archiver::Archiver>, Error> {
+    let volume_size = matches
+        .value_of("VOLUME_SIZE")
+        .map(|volume_size| {
+            let (size, multiplier) = match volume_size.chars().last() {
+                Some('K') | Some('k') => (&volume_size[..volume_size.len() - 1], 1 << 10),
+                Some('M') | Some('m') => (&volume_size[..volume_size.len() - 1], 1 << 20),
+                Some('G') | Some('g') => (&volume_size[..volume_size.len() - 1], 1 << 30),
+                _ => (volume_size, 1),
+            };
+            size.parse::<u64>()
+                .ok()
+                .filter(|size| *size > 0)
+                .map(|size| size * multiplier)
+                .ok_or_else(|| Error::Config(String::from("Volume size is not a valid size")))
+        })
+        .transpose()?;
+    archiver::new(
+        matches
+            .value_of("ARCHIVER")
+            .or(config.archiver.as_deref())
+            .unwrap_or(archiver::BORG),
+        volume_size,
+    )
+    .map_err(|error| Error::Config(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Root;
+    use std::env;
+
+    const UUID: &str = "b2d2880b-776a-4dbf-a5dc-a34e69b381b2";
+
+    #[test]
+    fn get_config_overrides_root_compression_only_from_the_flag() {
+        let mut config = Config {
+            compression: Some(String::from("zstd,3")),
+            ..Config::default()
+        };
+        let root = Root {
+            compression: Some(String::from("none")),
+            ..Root::default()
+        };
+        config.roots.insert(String::from("/data"), root);
+
+        env::remove_var("BORG_COMPRESSION");
+        let matches = create_subcommand()
+            .get_matches_from_safe(vec!["create", UUID])
+            .unwrap();
+        let default = get_config(&config, &matches).unwrap();
+        assert_eq!(default.compression("/data/a"), Some("none"));
+        assert_eq!(default.compression("/home"), Some("zstd,3"));
+
+        env::set_var("BORG_COMPRESSION", "lz4");
+        let matches = create_subcommand()
+            .get_matches_from_safe(vec!["create", UUID])
+            .unwrap();
+        let from_env = get_config(&config, &matches).unwrap();
+        let matches = create_subcommand()
+            .get_matches_from_safe(vec!["create", "-c", "zlib", UUID])
+            .unwrap();
+        let from_flag = get_config(&config, &matches).unwrap();
+        env::remove_var("BORG_COMPRESSION");
+        assert_eq!(from_env.compression("/data/a"), Some("none"));
+        assert_eq!(from_env.compression("/home"), Some("lz4"));
+        assert_eq!(from_flag.compression("/data/a"), Some("zlib"));
+        assert_eq!(from_flag.compression("/home"), Some("zlib"));
     }
 }
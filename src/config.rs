@@ -0,0 +1,293 @@
+use super::archiver;
+use super::error::Error;
+use super::exclusion::Filter;
+use super::model::RetentionPolicy;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+const CONFIG_FILE: &str = "permafrust/config.toml";
+const DEFAULT_MARGIN: f64 = 5.0;
+
+/// Settings read from `$XDG_CONFIG_HOME/permafrust/config.toml` or `--config`.
+/// Each setting is taken from its command line flag first, then from its
+/// environment variable, including those loaded from `.env`, then from here.
+/// The exception is per-root compression: `BORG_COMPRESSION` only replaces
+/// the default, so roots keep their own, while `--compression` wins over both.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database_url: Option<String>,
+    pub archiver: Option<String>,
+    /// Default repository, either a name from `repos` or a location or label
+    pub repo: Option<String>,
+    pub compression: Option<String>,
+    pub encryption: Option<String>,
+    /// Percentage of the repository filesystem to keep free after archiving
+    pub margin: f64,
+    /// Estimate sizes from a dry run with archivers reporting its statistics,
+    /// only restic does, others sample the compression ratio without one
+    pub estimate_dry_run: bool,
+    pub retention: Option<Retention>,
+    /// Gitignore-style patterns excluded from every directory
+    pub exclude: Vec<String>,
+    pub hooks: Hooks,
+    pub repos: BTreeMap<String, Repo>,
+    /// Settings for directories under each root path
+    pub roots: BTreeMap<String, Root>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Repo {
+    pub location: String,
+    pub encryption: Option<String>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Root {
+    /// Wins over `BORG_COMPRESSION` but not over `--compression`
+    pub compression: Option<String>,
+    pub retention: Option<Retention>,
+    /// Patterns added after the global ones, so `!` can re-include entries
+    pub exclude: Vec<String>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    pub keep_within: Option<String>,
+    pub keep_last: Option<i32>,
+    pub keep_daily: Option<i32>,
+    pub keep_weekly: Option<i32>,
+    pub keep_monthly: Option<i32>,
+    pub keep_yearly: Option<i32>,
+}
+
+impl Retention {
+    fn to_retention_policy(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            id: Uuid::nil(),
+            root_directory_id: None,
+            directory_id: None,
+            keep_within: self.keep_within.clone(),
+            keep_last: self.keep_last,
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+            keep_yearly: self.keep_yearly,
+        }
+    }
+}
+
+/// Shell commands run around archive creation, with `PERMAFRUST_DIRECTORY`,
+/// `PERMAFRUST_REPO` and, after creation, `PERMAFRUST_ARCHIVE` set.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_create: Option<String>,
+    pub post_create: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            database_url: None,
+            archiver: None,
+            repo: None,
+            compression: None,
+            encryption: None,
+            margin: DEFAULT_MARGIN,
+            estimate_dry_run: false,
+            retention: None,
+            exclude: Vec::new(),
+            hooks: Hooks::default(),
+            repos: BTreeMap::new(),
+            roots: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: Option<&str>) -> Result<Config, Error> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if !required && error.kind() == ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(error) => {
+                return Err(Error::Config(format!(
+                    "Cannot read {}: {}",
+                    path.display(),
+                    error
+                )))
+            }
+        };
+        let config: Config = toml::from_str(&contents)
+            .map_err(|error| Error::Config(format!("Invalid {}: {}", path.display(), error)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(0.0..100.0).contains(&self.margin) {
+            return Err(Error::Config(String::from(
+                "Margin must be a percentage between 0 and 100",
+            )));
+        }
+        if let Some(archiver) = &self.archiver {
+            if !archiver::ARCHIVERS.contains(&archiver.as_str()) {
+                return Err(Error::Config(format!("Unknown archiver {}", archiver)));
+            }
+        }
+        for (path, root) in &self.roots {
+            Filter::new(path, &root.exclude).map_err(|error| {
+                Error::Config(format!("Invalid exclude in {}: {}", path, error))
+            })?;
+        }
+        Filter::new("/", &self.exclude)
+            .map_err(|error| Error::Config(format!("Invalid exclude: {}", error)))?;
+        Ok(())
+    }
+
+    /// Resolves a repository name from the configuration, leaving locations
+    /// and catalog labels untouched.
+    pub fn repo_location<'a>(&'a self, repo: &'a str) -> &'a str {
+        self.repos
+            .get(repo)
+            .map_or(repo, |repository| repository.location.as_str())
+    }
+
+    pub fn encryption(&self, repo: &str) -> Option<&str> {
+        self.repos
+            .get(repo)
+            .and_then(|repository| repository.encryption.as_deref())
+            .or(self.encryption.as_deref())
+    }
+
+    pub fn compression(&self, path: &str) -> Option<&str> {
+        self.root(path)
+            .and_then(|root| root.compression.as_deref())
+            .or(self.compression.as_deref())
+    }
+
+    /// The retention set for the root holding `path`, if any.
+    pub fn root_retention_policy(&self, path: &str) -> Option<RetentionPolicy> {
+        self.root(path)
+            .and_then(|root| root.retention.as_ref())
+            .map(Retention::to_retention_policy)
+    }
+
+    pub fn retention_policy(&self) -> Option<RetentionPolicy> {
+        self.retention.as_ref().map(Retention::to_retention_policy)
+    }
+
+    pub fn exclude(&self, path: &str) -> Vec<String> {
+        let mut patterns = self.exclude.clone();
+        if let Some(root) = self.root(path) {
+            patterns.extend(root.exclude.iter().cloned());
+        }
+        patterns
+    }
+
+    fn root(&self, path: &str) -> Option<&Root> {
+        self.roots
+            .iter()
+            .filter(|(root_path, _)| {
+                let root_path = root_path.trim_end_matches('/');
+                path == root_path
+                    || (path.starts_with(root_path) && path[root_path.len()..].starts_with('/'))
+            })
+            .max_by_key(|(root_path, _)| root_path.len())
+            .map(|(_, root)| root)
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|path| path.join(CONFIG_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_config(roots: &[(&str, &str)]) -> Config {
+        let roots = roots
+            .iter()
+            .map(|(path, compression)| {
+                let root = Root {
+                    compression: Some(compression.to_string()),
+                    ..Root::default()
+                };
+                (path.to_string(), root)
+            })
+            .collect();
+        Config {
+            compression: Some(String::from("zstd,3")),
+            roots,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn root_matches_whole_path_components() {
+        let config = get_config(&[("/data", "lz4")]);
+        assert_eq!(config.compression("/data"), Some("lz4"));
+        assert_eq!(config.compression("/data/photos"), Some("lz4"));
+        assert_eq!(config.compression("/database"), Some("zstd,3"));
+        assert_eq!(config.compression("/"), Some("zstd,3"));
+    }
+
+    #[test]
+    fn root_ignores_trailing_slashes() {
+        let config = get_config(&[("/data/", "lz4")]);
+        assert_eq!(config.compression("/data"), Some("lz4"));
+        assert_eq!(config.compression("/data/photos"), Some("lz4"));
+        assert_eq!(config.compression("/database"), Some("zstd,3"));
+    }
+
+    #[test]
+    fn root_prefers_longest_match() {
+        let config = get_config(&[("/data", "lz4"), ("/data/photos", "none"), ("/", "zlib")]);
+        assert_eq!(config.compression("/data/photos/2020"), Some("none"));
+        assert_eq!(config.compression("/data/photos2"), Some("lz4"));
+        assert_eq!(config.compression("/home"), Some("zlib"));
+    }
+
+    #[test]
+    fn root_settings_extend_global_ones() {
+        let mut config = get_config(&[("/data", "lz4")]);
+        config.exclude = vec![String::from("*.tmp")];
+        config.roots.get_mut("/data").unwrap().exclude = vec![String::from("!keep.tmp")];
+        config.retention = Some(Retention {
+            keep_last: Some(3),
+            ..Retention::default()
+        });
+        assert_eq!(config.exclude("/data/a"), vec!["*.tmp", "!keep.tmp"]);
+        assert_eq!(config.exclude("/home"), vec!["*.tmp"]);
+        config.roots.get_mut("/data").unwrap().retention = Some(Retention {
+            keep_last: Some(1),
+            ..Retention::default()
+        });
+        let policy = config.root_retention_policy("/data/a").unwrap();
+        assert_eq!(policy.keep_last, Some(1));
+        assert!(config.root_retention_policy("/home").is_none());
+        assert_eq!(config.retention_policy().unwrap().keep_last, Some(3));
+    }
+}
@@ -0,0 +1,981 @@
+/// Implements `Catalog` for a sqlx connection type. The query text and row
+/// mapping are shared by every backend; each backend only supplies its
+/// null-safe comparison operator and a `create_manifest_entries` function,
+/// and must have the catalog types in scope where the macro is invoked.
+macro_rules! impl_catalog {
+    ($connection:ty, is_not_distinct_from = $is_not_distinct_from:literal) => {
+        #[async_trait]
+        impl Catalog for $connection {
+            async fn create_root_directory(
+                &mut self,
+                path: &str,
+                depth: i16,
+                fingerprint: &str,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    INSERT INTO root_directories (id, path, depth, fingerprint)
+                    VALUES ($1, $2, $3, $4)
+                    ",
+                )
+                .bind(Uuid::new_v4())
+                .bind(path)
+                .bind(depth)
+                .bind(fingerprint)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn update_root_directory(
+                &mut self,
+                id: &Uuid,
+                fingerprint: &str,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    UPDATE root_directories
+                    SET fingerprint = $2
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .bind(fingerprint)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_root_directories(
+                &mut self,
+            ) -> Result<Vec<RootDirectory>, Error> {
+                Ok(sqlx::query_as::<_, RootDirectory>(
+                    "
+                    SELECT *
+                    FROM root_directories
+                    ORDER BY path
+                    ",
+                )
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_root_directory_by_path(
+                &mut self,
+                path: &str,
+            ) -> Result<Option<RootDirectory>, Error> {
+                Ok(sqlx::query_as::<_, RootDirectory>(
+                    "
+                    SELECT *
+                    FROM root_directories
+                    WHERE path = $1
+                    ",
+                )
+                .bind(path)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn delete_root_directory(&mut self, id: &Uuid) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM root_directories
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn create_directory(
+                &mut self,
+                path: &str,
+                blake3_hash: &str,
+                fingerprint: &str,
+                root_directory_id: &Uuid,
+            ) -> Result<Uuid, Error> {
+                let id = Uuid::new_v4();
+                sqlx::query(
+                    "
+                    INSERT INTO directories (id, path, blake3_hash, fingerprint, root_directory_id)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ",
+                )
+                .bind(id)
+                .bind(path)
+                .bind(blake3_hash)
+                .bind(fingerprint)
+                .bind(root_directory_id)
+                .execute(self)
+                .await?;
+                Ok(id)
+            }
+
+            async fn update_directory(
+                &mut self,
+                id: &Uuid,
+                blake3_hash: &str,
+                fingerprint: &str,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    UPDATE directories
+                    SET blake3_hash = $2, fingerprint = $3, dirty = FALSE
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .bind(blake3_hash)
+                .bind(fingerprint)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn update_directory_dirty(
+                &mut self,
+                id: &Uuid,
+                dirty: bool,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    UPDATE directories
+                    SET dirty = $2
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .bind(dirty)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_directories(&mut self) -> Result<Vec<Directory>, Error> {
+                Ok(sqlx::query_as::<_, Directory>(
+                    "
+                    SELECT *
+                    FROM directories
+                    ORDER BY path
+                    ",
+                )
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_dirty_directories(&mut self) -> Result<Vec<Directory>, Error> {
+                Ok(sqlx::query_as::<_, Directory>(
+                    "
+                    SELECT *
+                    FROM directories
+                    WHERE dirty
+                    ORDER BY path
+                    ",
+                )
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_directories_without_archives(
+                &mut self,
+            ) -> Result<Vec<Directory>, Error> {
+                Ok(sqlx::query_as::<_, Directory>(
+                    "
+                    SELECT *
+                    FROM directories d
+                    WHERE NOT EXISTS (
+                        SELECT a.id
+                        FROM archives a
+                        WHERE a.directory_id = d.id
+                    )
+                    ORDER BY d.path
+                    ",
+                )
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_directories_with_archives_by_root_directory_id(
+                &mut self,
+                root_directory_id: &Uuid,
+            ) -> Result<Vec<Directory>, Error> {
+                Ok(sqlx::query_as::<_, Directory>(
+                    "
+                    SELECT *
+                    FROM directories d
+                    WHERE d.root_directory_id = $1
+                    AND EXISTS (
+                        SELECT a.id
+                        FROM archives a
+                        WHERE a.directory_id = d.id
+                    )
+                    ORDER BY d.path
+                    ",
+                )
+                .bind(root_directory_id)
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_directories_without_archives_by_root_directory_id(
+                &mut self,
+                root_directory_id: &Uuid,
+            ) -> Result<Vec<Directory>, Error> {
+                Ok(sqlx::query_as::<_, Directory>(
+                    "
+                    SELECT *
+                    FROM directories d
+                    WHERE root_directory_id = $1
+                    AND NOT EXISTS (
+                        SELECT a.id
+                        FROM archives a
+                        WHERE a.directory_id = d.id
+                    )
+                    ORDER BY d.path
+                    ",
+                )
+                .bind(root_directory_id)
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_directory_by_id(
+                &mut self,
+                id: &Uuid,
+            ) -> Result<Directory, Error> {
+                sqlx::query_as::<_, Directory>(
+                    "
+                    SELECT *
+                    FROM directories
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .fetch_optional(self)
+                .await?
+                .ok_or_else(|| Error::NotFound(format!("No directory with id {}", id)))
+            }
+
+            async fn find_directory_by_path(
+                &mut self,
+                path: &str,
+            ) -> Result<Option<Directory>, Error> {
+                Ok(sqlx::query_as::<_, Directory>(
+                    "
+                    SELECT *
+                    FROM directories
+                    WHERE path = $1
+                    ",
+                )
+                .bind(path)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn delete_directory(&mut self, id: &Uuid) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM directories
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn create_archive(&mut self, archive: &Archive) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    INSERT INTO archives (id, name, repo_id, archive_id, created_date, blake3_hash, fingerprint, directory_id)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                    "
+                )
+                .bind(archive.id)
+                .bind(&archive.name)
+                .bind(&archive.repo_id)
+                .bind(&archive.archive_id)
+                .bind(archive.created_date)
+                .bind(&archive.blake3_hash)
+                .bind(&archive.fingerprint)
+                .bind(archive.directory_id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_archive_by_id(&mut self, id: &Uuid) -> Result<Archive, Error> {
+                sqlx::query_as::<_, Archive>(
+                    "
+                    SELECT *
+                    FROM archives
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .fetch_optional(self)
+                .await?
+                .ok_or_else(|| Error::NotFound(format!("No archive with id {}", id)))
+            }
+
+            async fn find_archives_by_repo_id(
+                &mut self,
+                repo_id: &str,
+            ) -> Result<Vec<Archive>, Error> {
+                Ok(sqlx::query_as::<_, Archive>(
+                    "
+                    SELECT *
+                    FROM archives
+                    WHERE repo_id = $1
+                    ORDER BY created_date DESC
+                    ",
+                )
+                .bind(repo_id)
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_archives_by_directory_id(
+                &mut self,
+                directory_id: &Uuid,
+            ) -> Result<Vec<Archive>, Error> {
+                Ok(sqlx::query_as::<_, Archive>(
+                    "
+                    SELECT *
+                    FROM archives
+                    WHERE directory_id = $1
+                    ORDER BY created_date DESC
+                    ",
+                )
+                .bind(directory_id)
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_latest_archive_by_directory_id(
+                &mut self,
+                directory_id: &Uuid,
+            ) -> Result<Option<Archive>, Error> {
+                Ok(sqlx::query_as::<_, Archive>(
+                    "
+                    SELECT *
+                    FROM archives
+                    WHERE directory_id = $1
+                    ORDER BY created_date DESC
+                    LIMIT 1
+                    ",
+                )
+                .bind(directory_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn find_latest_archive_by_directory_id_and_repo_id(
+                &mut self,
+                directory_id: &Uuid,
+                repo_id: &str,
+            ) -> Result<Option<Archive>, Error> {
+                Ok(sqlx::query_as::<_, Archive>(
+                    "
+                    SELECT *
+                    FROM archives
+                    WHERE directory_id = $1
+                    AND repo_id = $2
+                    ORDER BY created_date DESC
+                    LIMIT 1
+                    ",
+                )
+                .bind(directory_id)
+                .bind(repo_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn find_repo_ids_by_directory_id(
+                &mut self,
+                directory_id: &Uuid,
+            ) -> Result<Vec<String>, Error> {
+                Ok(sqlx::query_as::<_, (String,)>(
+                    "
+                    SELECT DISTINCT repo_id
+                    FROM archives
+                    WHERE directory_id = $1
+                    ORDER BY repo_id
+                    ",
+                )
+                .bind(directory_id)
+                .fetch_all(self)
+                .await?
+                .into_iter()
+                .map(|(repo_id,)| repo_id)
+                .collect())
+            }
+
+            async fn find_archive_by_repo_id_and_archive_id(
+                &mut self,
+                repo_id: &str,
+                archive_id: &str,
+            ) -> Result<Option<Archive>, Error> {
+                Ok(sqlx::query_as::<_, Archive>(
+                    "
+                    SELECT *
+                    FROM archives
+                    WHERE repo_id = $1
+                    AND archive_id = $2
+                    ",
+                )
+                .bind(repo_id)
+                .bind(archive_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn delete_archive(&mut self, id: &Uuid) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM archives
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn create_archive_stats(
+                &mut self,
+                archive_stats: &ArchiveStats,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    INSERT INTO archive_stats (archive_id, original_size, compressed_size, deduplicated_size, nfiles, duration)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    "
+                )
+                .bind(archive_stats.archive_id)
+                .bind(archive_stats.original_size)
+                .bind(archive_stats.compressed_size)
+                .bind(archive_stats.deduplicated_size)
+                .bind(archive_stats.nfiles)
+                .bind(archive_stats.duration)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_archive_stats_by_archive_id(
+                &mut self,
+                archive_id: &Uuid,
+            ) -> Result<Option<ArchiveStats>, Error> {
+                Ok(sqlx::query_as::<_, ArchiveStats>(
+                    "
+                    SELECT *
+                    FROM archive_stats
+                    WHERE archive_id = $1
+                    ",
+                )
+                .bind(archive_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn create_retention_policy(
+                &mut self,
+                retention_policy: &RetentionPolicy,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    INSERT INTO retention_policies (id, root_directory_id, directory_id, keep_within, keep_last, keep_daily, keep_weekly, keep_monthly, keep_yearly)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    "
+                )
+                .bind(retention_policy.id)
+                .bind(retention_policy.root_directory_id)
+                .bind(retention_policy.directory_id)
+                .bind(&retention_policy.keep_within)
+                .bind(retention_policy.keep_last)
+                .bind(retention_policy.keep_daily)
+                .bind(retention_policy.keep_weekly)
+                .bind(retention_policy.keep_monthly)
+                .bind(retention_policy.keep_yearly)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_retention_policy_by_scope(
+                &mut self,
+                root_directory_id: Option<&Uuid>,
+                directory_id: Option<&Uuid>,
+            ) -> Result<Option<RetentionPolicy>, Error> {
+                Ok(sqlx::query_as::<_, RetentionPolicy>(concat!(
+                    "
+                    SELECT *
+                    FROM retention_policies
+                    WHERE root_directory_id ", $is_not_distinct_from, " $1
+                    AND directory_id ", $is_not_distinct_from, " $2
+                    ",
+                ))
+                .bind(root_directory_id)
+                .bind(directory_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn delete_retention_policy(&mut self, id: &Uuid) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM retention_policies
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn create_repository(
+                &mut self,
+                label: &str,
+                location: &str,
+                repo_id: &str,
+                encryption: &str,
+                last_seen_date: &NaiveDateTime,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    INSERT INTO repositories (id, label, location, repo_id, encryption, last_seen_date)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ",
+                )
+                .bind(Uuid::new_v4())
+                .bind(label)
+                .bind(location)
+                .bind(repo_id)
+                .bind(encryption)
+                .bind(last_seen_date)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn update_repository_last_seen_date(
+                &mut self,
+                id: &Uuid,
+                last_seen_date: &NaiveDateTime,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    UPDATE repositories
+                    SET last_seen_date = $2
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .bind(last_seen_date)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_repositories(&mut self) -> Result<Vec<Repository>, Error> {
+                Ok(sqlx::query_as::<_, Repository>(
+                    "
+                    SELECT *
+                    FROM repositories
+                    ORDER BY label
+                    ",
+                )
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_repository_by_label(
+                &mut self,
+                label: &str,
+            ) -> Result<Option<Repository>, Error> {
+                Ok(sqlx::query_as::<_, Repository>(
+                    "
+                    SELECT *
+                    FROM repositories
+                    WHERE label = $1
+                    ",
+                )
+                .bind(label)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn find_repository_by_repo_id(
+                &mut self,
+                repo_id: &str,
+            ) -> Result<Option<Repository>, Error> {
+                Ok(sqlx::query_as::<_, Repository>(
+                    "
+                    SELECT *
+                    FROM repositories
+                    WHERE repo_id = $1
+                    ",
+                )
+                .bind(repo_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn delete_repository(&mut self, id: &Uuid) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM repositories
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn count_archives_by_repo_id(
+                &mut self,
+                repo_id: &str,
+            ) -> Result<i64, Error> {
+                Ok(sqlx::query_scalar(
+                    "
+                    SELECT COUNT(*)
+                    FROM archives
+                    WHERE repo_id = $1
+                    ",
+                )
+                .bind(repo_id)
+                .fetch_one(self)
+                .await?)
+            }
+
+            async fn update_repository_drive_id(
+                &mut self,
+                id: &Uuid,
+                drive_id: &Uuid,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    UPDATE repositories
+                    SET drive_id = $2
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .bind(drive_id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_repositories_by_drive_id(
+                &mut self,
+                drive_id: &Uuid,
+            ) -> Result<Vec<Repository>, Error> {
+                Ok(sqlx::query_as::<_, Repository>(
+                    "
+                    SELECT *
+                    FROM repositories
+                    WHERE drive_id = $1
+                    ORDER BY label
+                    ",
+                )
+                .bind(drive_id)
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn create_drive(
+                &mut self,
+                uuid: &str,
+                label: Option<&str>,
+                size: i64,
+                avail: i64,
+                last_mounted_date: &NaiveDateTime,
+            ) -> Result<Uuid, Error> {
+                let id = Uuid::new_v4();
+                sqlx::query(
+                    "
+                    INSERT INTO drives (id, uuid, label, size, avail, last_mounted_date)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ",
+                )
+                .bind(id)
+                .bind(uuid)
+                .bind(label)
+                .bind(size)
+                .bind(avail)
+                .bind(last_mounted_date)
+                .execute(self)
+                .await?;
+                Ok(id)
+            }
+
+            async fn update_drive(
+                &mut self,
+                id: &Uuid,
+                label: Option<&str>,
+                size: i64,
+                avail: i64,
+                last_mounted_date: &NaiveDateTime,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    UPDATE drives
+                    SET label = $2, size = $3, avail = $4, last_mounted_date = $5
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .bind(label)
+                .bind(size)
+                .bind(avail)
+                .bind(last_mounted_date)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_drives(&mut self) -> Result<Vec<Drive>, Error> {
+                Ok(sqlx::query_as::<_, Drive>(
+                    "
+                    SELECT *
+                    FROM drives
+                    ORDER BY label, uuid
+                    ",
+                )
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn find_drive_by_id(&mut self, id: &Uuid) -> Result<Drive, Error> {
+                sqlx::query_as::<_, Drive>(
+                    "
+                    SELECT *
+                    FROM drives
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .fetch_optional(self)
+                .await?
+                .ok_or_else(|| Error::NotFound(format!("No drive with id {}", id)))
+            }
+
+            async fn find_drive_by_uuid(
+                &mut self,
+                uuid: &str,
+            ) -> Result<Option<Drive>, Error> {
+                Ok(sqlx::query_as::<_, Drive>(
+                    "
+                    SELECT *
+                    FROM drives
+                    WHERE uuid = $1
+                    ",
+                )
+                .bind(uuid)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn create_replication_policy(
+                &mut self,
+                replication_policy: &ReplicationPolicy,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    INSERT INTO replication_policies (id, root_directory_id, directory_id, copies)
+                    VALUES ($1, $2, $3, $4)
+                    ",
+                )
+                .bind(replication_policy.id)
+                .bind(replication_policy.root_directory_id)
+                .bind(replication_policy.directory_id)
+                .bind(replication_policy.copies)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_replication_policy_by_scope(
+                &mut self,
+                root_directory_id: Option<&Uuid>,
+                directory_id: Option<&Uuid>,
+            ) -> Result<Option<ReplicationPolicy>, Error> {
+                Ok(sqlx::query_as::<_, ReplicationPolicy>(concat!(
+                    "
+                    SELECT *
+                    FROM replication_policies
+                    WHERE root_directory_id ", $is_not_distinct_from, " $1
+                    AND directory_id ", $is_not_distinct_from, " $2
+                    ",
+                ))
+                .bind(root_directory_id)
+                .bind(directory_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn delete_replication_policy(
+                &mut self,
+                id: &Uuid,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM replication_policies
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn create_exclusion_policy(
+                &mut self,
+                exclusion_policy: &ExclusionPolicy,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    INSERT INTO exclusion_policies (id, root_directory_id, directory_id, patterns)
+                    VALUES ($1, $2, $3, $4)
+                    ",
+                )
+                .bind(exclusion_policy.id)
+                .bind(exclusion_policy.root_directory_id)
+                .bind(exclusion_policy.directory_id)
+                .bind(&exclusion_policy.patterns)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_exclusion_policy_by_scope(
+                &mut self,
+                root_directory_id: Option<&Uuid>,
+                directory_id: Option<&Uuid>,
+            ) -> Result<Option<ExclusionPolicy>, Error> {
+                Ok(sqlx::query_as::<_, ExclusionPolicy>(concat!(
+                    "
+                    SELECT *
+                    FROM exclusion_policies
+                    WHERE root_directory_id ", $is_not_distinct_from, " $1
+                    AND directory_id ", $is_not_distinct_from, " $2
+                    ",
+                ))
+                .bind(root_directory_id)
+                .bind(directory_id)
+                .fetch_optional(self)
+                .await?)
+            }
+
+            async fn delete_exclusion_policy(&mut self, id: &Uuid) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM exclusion_policies
+                    WHERE id = $1
+                    ",
+                )
+                .bind(id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn create_manifest_entries(
+                &mut self,
+                directory_id: &Uuid,
+                manifest_entries: &[ManifestEntry],
+            ) -> Result<(), Error> {
+                create_manifest_entries(self, directory_id, manifest_entries).await
+            }
+
+            async fn copy_manifest_entries(
+                &mut self,
+                directory_id: &Uuid,
+                source_archive_id: Option<&Uuid>,
+                archive_id: &Uuid,
+            ) -> Result<(), Error> {
+                sqlx::query(concat!(
+                    "
+                    INSERT INTO manifest_entries (directory_id, archive_id, path, size, modified_date, inode, blake3_hash)
+                    SELECT directory_id, $3, path, size, modified_date, inode, blake3_hash
+                    FROM manifest_entries
+                    WHERE directory_id = $1
+                    AND archive_id ", $is_not_distinct_from, " $2
+                    ",
+                ))
+                .bind(directory_id)
+                .bind(source_archive_id)
+                .bind(archive_id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+
+            async fn find_manifest_entries_by_directory_id_and_archive_id(
+                &mut self,
+                directory_id: &Uuid,
+                archive_id: Option<&Uuid>,
+            ) -> Result<Vec<ManifestEntry>, Error> {
+                Ok(sqlx::query_as::<_, ManifestEntry>(concat!(
+                    "
+                    SELECT path, size, modified_date, inode, blake3_hash
+                    FROM manifest_entries
+                    WHERE directory_id = $1
+                    AND archive_id ", $is_not_distinct_from, " $2
+                    ORDER BY path
+                    ",
+                ))
+                .bind(directory_id)
+                .bind(archive_id)
+                .fetch_all(self)
+                .await?)
+            }
+
+            async fn exists_manifest_entries_by_directory_id(
+                &mut self,
+                directory_id: &Uuid,
+            ) -> Result<bool, Error> {
+                Ok(sqlx::query_scalar(
+                    "
+                    SELECT EXISTS (
+                        SELECT path
+                        FROM manifest_entries
+                        WHERE directory_id = $1
+                        AND archive_id IS NULL
+                    )
+                    ",
+                )
+                .bind(directory_id)
+                .fetch_one(self)
+                .await?)
+            }
+
+            async fn delete_manifest_entries_by_directory_id(
+                &mut self,
+                directory_id: &Uuid,
+            ) -> Result<(), Error> {
+                sqlx::query(
+                    "
+                    DELETE FROM manifest_entries
+                    WHERE directory_id = $1
+                    AND archive_id IS NULL
+                    ",
+                )
+                .bind(directory_id)
+                .execute(self)
+                .await?;
+                Ok(())
+            }
+        }
+    };
+}
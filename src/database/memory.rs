@@ -0,0 +1,832 @@
+use super::super::catalog::Catalog;
+use super::super::error::Error;
+use super::super::model::*;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use std::cmp::Reverse;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub struct MemoryCatalog {
+    root_directories: Vec<RootDirectory>,
+    directories: Vec<Directory>,
+    archives: Vec<Archive>,
+    archive_stats: Vec<ArchiveStats>,
+    retention_policies: Vec<RetentionPolicy>,
+    repositories: Vec<Repository>,
+    drives: Vec<Drive>,
+    replication_policies: Vec<ReplicationPolicy>,
+    exclusion_policies: Vec<ExclusionPolicy>,
+    manifest_entries: Vec<(Uuid, Option<Uuid>, ManifestEntry)>,
+}
+
+impl MemoryCatalog {
+    fn has_archives(&self, directory_id: &Uuid) -> bool {
+        self.archives
+            .iter()
+            .any(|a| a.directory_id.as_ref() == Some(directory_id))
+    }
+
+    fn find_archives(&self, predicate: impl Fn(&Archive) -> bool) -> Vec<Archive> {
+        let mut archives: Vec<Archive> = self
+            .archives
+            .iter()
+            .filter(|a| predicate(a))
+            .cloned()
+            .collect();
+        archives.sort_by_key(|a| Reverse(a.created_date));
+        archives
+    }
+
+    fn find_directories_by(&self, predicate: impl Fn(&Directory) -> bool) -> Vec<Directory> {
+        let mut directories: Vec<Directory> = self
+            .directories
+            .iter()
+            .filter(|d| predicate(d))
+            .cloned()
+            .collect();
+        directories.sort_by(|a, b| a.path.cmp(&b.path));
+        directories
+    }
+
+    fn find_repositories_by(&self, predicate: impl Fn(&Repository) -> bool) -> Vec<Repository> {
+        let mut repositories: Vec<Repository> = self
+            .repositories
+            .iter()
+            .filter(|r| predicate(r))
+            .cloned()
+            .collect();
+        repositories.sort_by(|a, b| a.label.cmp(&b.label));
+        repositories
+    }
+}
+
+#[async_trait]
+impl Catalog for MemoryCatalog {
+    async fn create_root_directory(
+        &mut self,
+        path: &str,
+        depth: i16,
+        fingerprint: &str,
+    ) -> Result<(), Error> {
+        self.root_directories.push(RootDirectory {
+            id: Uuid::new_v4(),
+            path: path.to_string(),
+            depth,
+            fingerprint: fingerprint.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn update_root_directory(&mut self, id: &Uuid, fingerprint: &str) -> Result<(), Error> {
+        if let Some(root_directory) = self.root_directories.iter_mut().find(|r| &r.id == id) {
+            root_directory.fingerprint = fingerprint.to_string();
+        }
+        Ok(())
+    }
+
+    async fn find_root_directories(&mut self) -> Result<Vec<RootDirectory>, Error> {
+        let mut root_directories = self.root_directories.clone();
+        root_directories.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(root_directories)
+    }
+
+    async fn find_root_directory_by_path(
+        &mut self,
+        path: &str,
+    ) -> Result<Option<RootDirectory>, Error> {
+        Ok(self
+            .root_directories
+            .iter()
+            .find(|r| r.path == path)
+            .cloned())
+    }
+
+    async fn delete_root_directory(&mut self, id: &Uuid) -> Result<(), Error> {
+        let directory_ids: Vec<Uuid> = self
+            .directories
+            .iter()
+            .filter(|d| &d.root_directory_id == id)
+            .map(|d| d.id)
+            .collect();
+        for directory_id in directory_ids {
+            self.delete_directory(&directory_id).await?;
+        }
+        self.retention_policies
+            .retain(|p| p.root_directory_id.as_ref() != Some(id));
+        self.replication_policies
+            .retain(|p| p.root_directory_id.as_ref() != Some(id));
+        self.exclusion_policies
+            .retain(|p| p.root_directory_id.as_ref() != Some(id));
+        self.root_directories.retain(|r| &r.id != id);
+        Ok(())
+    }
+
+    async fn create_directory(
+        &mut self,
+        path: &str,
+        blake3_hash: &str,
+        fingerprint: &str,
+        root_directory_id: &Uuid,
+    ) -> Result<Uuid, Error> {
+        let id = Uuid::new_v4();
+        self.directories.push(Directory {
+            id,
+            path: path.to_string(),
+            blake3_hash: blake3_hash.to_string(),
+            root_directory_id: *root_directory_id,
+            fingerprint: fingerprint.to_string(),
+            dirty: false,
+        });
+        Ok(id)
+    }
+
+    async fn update_directory(
+        &mut self,
+        id: &Uuid,
+        blake3_hash: &str,
+        fingerprint: &str,
+    ) -> Result<(), Error> {
+        if let Some(directory) = self.directories.iter_mut().find(|d| &d.id == id) {
+            directory.blake3_hash = blake3_hash.to_string();
+            directory.fingerprint = fingerprint.to_string();
+            directory.dirty = false;
+        }
+        Ok(())
+    }
+
+    async fn update_directory_dirty(&mut self, id: &Uuid, dirty: bool) -> Result<(), Error> {
+        if let Some(directory) = self.directories.iter_mut().find(|d| &d.id == id) {
+            directory.dirty = dirty;
+        }
+        Ok(())
+    }
+
+    async fn find_directories(&mut self) -> Result<Vec<Directory>, Error> {
+        Ok(self.find_directories_by(|_| true))
+    }
+
+    async fn find_dirty_directories(&mut self) -> Result<Vec<Directory>, Error> {
+        Ok(self.find_directories_by(|d| d.dirty))
+    }
+
+    async fn find_directories_without_archives(&mut self) -> Result<Vec<Directory>, Error> {
+        Ok(self.find_directories_by(|d| !self.has_archives(&d.id)))
+    }
+
+    async fn find_directories_with_archives_by_root_directory_id(
+        &mut self,
+        root_directory_id: &Uuid,
+    ) -> Result<Vec<Directory>, Error> {
+        Ok(self.find_directories_by(|d| {
+            &d.root_directory_id == root_directory_id && self.has_archives(&d.id)
+        }))
+    }
+
+    async fn find_directories_without_archives_by_root_directory_id(
+        &mut self,
+        root_directory_id: &Uuid,
+    ) -> Result<Vec<Directory>, Error> {
+        Ok(self.find_directories_by(|d| {
+            &d.root_directory_id == root_directory_id && !self.has_archives(&d.id)
+        }))
+    }
+
+    async fn find_directory_by_id(&mut self, id: &Uuid) -> Result<Directory, Error> {
+        self.directories
+            .iter()
+            .find(|d| &d.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("No directory with id {}", id)))
+    }
+
+    async fn find_directory_by_path(&mut self, path: &str) -> Result<Option<Directory>, Error> {
+        Ok(self.directories.iter().find(|d| d.path == path).cloned())
+    }
+
+    async fn delete_directory(&mut self, id: &Uuid) -> Result<(), Error> {
+        for archive in self.archives.iter_mut() {
+            if archive.directory_id.as_ref() == Some(id) {
+                archive.directory_id = None;
+            }
+        }
+        self.retention_policies
+            .retain(|p| p.directory_id.as_ref() != Some(id));
+        self.replication_policies
+            .retain(|p| p.directory_id.as_ref() != Some(id));
+        self.exclusion_policies
+            .retain(|p| p.directory_id.as_ref() != Some(id));
+        self.manifest_entries
+            .retain(|(directory_id, _, _)| directory_id != id);
+        self.directories.retain(|d| &d.id != id);
+        Ok(())
+    }
+
+    async fn create_archive(&mut self, archive: &Archive) -> Result<(), Error> {
+        if self
+            .archives
+            .iter()
+            .any(|a| a.repo_id == archive.repo_id && a.name == archive.name)
+        {
+            return Err(Error::Conflict(format!(
+                "Archive {} already exists in repository {}",
+                archive.name, archive.repo_id
+            )));
+        }
+        self.archives.push(archive.clone());
+        Ok(())
+    }
+
+    async fn find_archive_by_id(&mut self, id: &Uuid) -> Result<Archive, Error> {
+        self.archives
+            .iter()
+            .find(|a| &a.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("No archive with id {}", id)))
+    }
+
+    async fn find_archives_by_repo_id(&mut self, repo_id: &str) -> Result<Vec<Archive>, Error> {
+        Ok(self.find_archives(|a| a.repo_id == repo_id))
+    }
+
+    async fn find_archives_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<Vec<Archive>, Error> {
+        Ok(self.find_archives(|a| a.directory_id.as_ref() == Some(directory_id)))
+    }
+
+    async fn find_latest_archive_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<Option<Archive>, Error> {
+        Ok(self
+            .find_archives(|a| a.directory_id.as_ref() == Some(directory_id))
+            .into_iter()
+            .next())
+    }
+
+    async fn find_latest_archive_by_directory_id_and_repo_id(
+        &mut self,
+        directory_id: &Uuid,
+        repo_id: &str,
+    ) -> Result<Option<Archive>, Error> {
+        Ok(self
+            .find_archives(|a| {
+                a.directory_id.as_ref() == Some(directory_id) && a.repo_id == repo_id
+            })
+            .into_iter()
+            .next())
+    }
+
+    async fn find_repo_ids_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<Vec<String>, Error> {
+        let mut repo_ids: Vec<String> = self
+            .find_archives(|a| a.directory_id.as_ref() == Some(directory_id))
+            .into_iter()
+            .map(|a| a.repo_id)
+            .collect();
+        repo_ids.sort();
+        repo_ids.dedup();
+        Ok(repo_ids)
+    }
+
+    async fn find_archive_by_repo_id_and_archive_id(
+        &mut self,
+        repo_id: &str,
+        archive_id: &str,
+    ) -> Result<Option<Archive>, Error> {
+        Ok(self
+            .archives
+            .iter()
+            .find(|a| a.repo_id == repo_id && a.archive_id == archive_id)
+            .cloned())
+    }
+
+    async fn delete_archive(&mut self, id: &Uuid) -> Result<(), Error> {
+        self.manifest_entries
+            .retain(|(_, archive_id, _)| archive_id.as_ref() != Some(id));
+        self.archive_stats.retain(|s| &s.archive_id != id);
+        self.archives.retain(|a| &a.id != id);
+        Ok(())
+    }
+
+    async fn create_archive_stats(&mut self, archive_stats: &ArchiveStats) -> Result<(), Error> {
+        self.archive_stats.push(archive_stats.clone());
+        Ok(())
+    }
+
+    async fn find_archive_stats_by_archive_id(
+        &mut self,
+        archive_id: &Uuid,
+    ) -> Result<Option<ArchiveStats>, Error> {
+        Ok(self
+            .archive_stats
+            .iter()
+            .find(|s| &s.archive_id == archive_id)
+            .cloned())
+    }
+
+    async fn create_retention_policy(
+        &mut self,
+        retention_policy: &RetentionPolicy,
+    ) -> Result<(), Error> {
+        self.retention_policies.push(retention_policy.clone());
+        Ok(())
+    }
+
+    async fn find_retention_policy_by_scope(
+        &mut self,
+        root_directory_id: Option<&Uuid>,
+        directory_id: Option<&Uuid>,
+    ) -> Result<Option<RetentionPolicy>, Error> {
+        Ok(self
+            .retention_policies
+            .iter()
+            .find(|p| {
+                p.root_directory_id.as_ref() == root_directory_id
+                    && p.directory_id.as_ref() == directory_id
+            })
+            .cloned())
+    }
+
+    async fn delete_retention_policy(&mut self, id: &Uuid) -> Result<(), Error> {
+        self.retention_policies.retain(|p| &p.id != id);
+        Ok(())
+    }
+
+    async fn create_repository(
+        &mut self,
+        label: &str,
+        location: &str,
+        repo_id: &str,
+        encryption: &str,
+        last_seen_date: &NaiveDateTime,
+    ) -> Result<(), Error> {
+        self.repositories.push(Repository {
+            id: Uuid::new_v4(),
+            label: label.to_string(),
+            location: location.to_string(),
+            repo_id: repo_id.to_string(),
+            encryption: encryption.to_string(),
+            last_seen_date: *last_seen_date,
+            drive_id: None,
+        });
+        Ok(())
+    }
+
+    async fn update_repository_last_seen_date(
+        &mut self,
+        id: &Uuid,
+        last_seen_date: &NaiveDateTime,
+    ) -> Result<(), Error> {
+        if let Some(repository) = self.repositories.iter_mut().find(|r| &r.id == id) {
+            repository.last_seen_date = *last_seen_date;
+        }
+        Ok(())
+    }
+
+    async fn find_repositories(&mut self) -> Result<Vec<Repository>, Error> {
+        Ok(self.find_repositories_by(|_| true))
+    }
+
+    async fn find_repository_by_label(&mut self, label: &str) -> Result<Option<Repository>, Error> {
+        Ok(self.repositories.iter().find(|r| r.label == label).cloned())
+    }
+
+    async fn find_repository_by_repo_id(
+        &mut self,
+        repo_id: &str,
+    ) -> Result<Option<Repository>, Error> {
+        Ok(self
+            .repositories
+            .iter()
+            .find(|r| r.repo_id == repo_id)
+            .cloned())
+    }
+
+    async fn delete_repository(&mut self, id: &Uuid) -> Result<(), Error> {
+        self.repositories.retain(|r| &r.id != id);
+        Ok(())
+    }
+
+    async fn count_archives_by_repo_id(&mut self, repo_id: &str) -> Result<i64, Error> {
+        Ok(self
+            .archives
+            .iter()
+            .filter(|a| a.repo_id == repo_id)
+            .count() as i64)
+    }
+
+    async fn update_repository_drive_id(
+        &mut self,
+        id: &Uuid,
+        drive_id: &Uuid,
+    ) -> Result<(), Error> {
+        if let Some(repository) = self.repositories.iter_mut().find(|r| &r.id == id) {
+            repository.drive_id = Some(*drive_id);
+        }
+        Ok(())
+    }
+
+    async fn find_repositories_by_drive_id(
+        &mut self,
+        drive_id: &Uuid,
+    ) -> Result<Vec<Repository>, Error> {
+        Ok(self.find_repositories_by(|r| r.drive_id.as_ref() == Some(drive_id)))
+    }
+
+    async fn create_drive(
+        &mut self,
+        uuid: &str,
+        label: Option<&str>,
+        size: i64,
+        avail: i64,
+        last_mounted_date: &NaiveDateTime,
+    ) -> Result<Uuid, Error> {
+        let id = Uuid::new_v4();
+        self.drives.push(Drive {
+            id,
+            uuid: uuid.to_string(),
+            label: label.map(|label| label.to_string()),
+            size,
+            avail,
+            last_mounted_date: *last_mounted_date,
+        });
+        Ok(id)
+    }
+
+    async fn update_drive(
+        &mut self,
+        id: &Uuid,
+        label: Option<&str>,
+        size: i64,
+        avail: i64,
+        last_mounted_date: &NaiveDateTime,
+    ) -> Result<(), Error> {
+        if let Some(drive) = self.drives.iter_mut().find(|d| &d.id == id) {
+            drive.label = label.map(|label| label.to_string());
+            drive.size = size;
+            drive.avail = avail;
+            drive.last_mounted_date = *last_mounted_date;
+        }
+        Ok(())
+    }
+
+    async fn find_drives(&mut self) -> Result<Vec<Drive>, Error> {
+        let mut drives = self.drives.clone();
+        drives.sort_by(|a, b| {
+            (a.label.is_none(), &a.label, &a.uuid).cmp(&(b.label.is_none(), &b.label, &b.uuid))
+        });
+        Ok(drives)
+    }
+
+    async fn find_drive_by_id(&mut self, id: &Uuid) -> Result<Drive, Error> {
+        self.drives
+            .iter()
+            .find(|d| &d.id == id)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("No drive with id {}", id)))
+    }
+
+    async fn find_drive_by_uuid(&mut self, uuid: &str) -> Result<Option<Drive>, Error> {
+        Ok(self.drives.iter().find(|d| d.uuid == uuid).cloned())
+    }
+
+    async fn create_replication_policy(
+        &mut self,
+        replication_policy: &ReplicationPolicy,
+    ) -> Result<(), Error> {
+        self.replication_policies.push(replication_policy.clone());
+        Ok(())
+    }
+
+    async fn find_replication_policy_by_scope(
+        &mut self,
+        root_directory_id: Option<&Uuid>,
+        directory_id: Option<&Uuid>,
+    ) -> Result<Option<ReplicationPolicy>, Error> {
+        Ok(self
+            .replication_policies
+            .iter()
+            .find(|p| {
+                p.root_directory_id.as_ref() == root_directory_id
+                    && p.directory_id.as_ref() == directory_id
+            })
+            .cloned())
+    }
+
+    async fn delete_replication_policy(&mut self, id: &Uuid) -> Result<(), Error> {
+        self.replication_policies.retain(|p| &p.id != id);
+        Ok(())
+    }
+
+    async fn create_exclusion_policy(
+        &mut self,
+        exclusion_policy: &ExclusionPolicy,
+    ) -> Result<(), Error> {
+        self.exclusion_policies.push(exclusion_policy.clone());
+        Ok(())
+    }
+
+    async fn find_exclusion_policy_by_scope(
+        &mut self,
+        root_directory_id: Option<&Uuid>,
+        directory_id: Option<&Uuid>,
+    ) -> Result<Option<ExclusionPolicy>, Error> {
+        Ok(self
+            .exclusion_policies
+            .iter()
+            .find(|p| {
+                p.root_directory_id.as_ref() == root_directory_id
+                    && p.directory_id.as_ref() == directory_id
+            })
+            .cloned())
+    }
+
+    async fn delete_exclusion_policy(&mut self, id: &Uuid) -> Result<(), Error> {
+        self.exclusion_policies.retain(|p| &p.id != id);
+        Ok(())
+    }
+
+    async fn create_manifest_entries(
+        &mut self,
+        directory_id: &Uuid,
+        manifest_entries: &[ManifestEntry],
+    ) -> Result<(), Error> {
+        for manifest_entry in manifest_entries {
+            self.manifest_entries
+                .push((*directory_id, None, manifest_entry.clone()));
+        }
+        Ok(())
+    }
+
+    async fn copy_manifest_entries(
+        &mut self,
+        directory_id: &Uuid,
+        source_archive_id: Option<&Uuid>,
+        archive_id: &Uuid,
+    ) -> Result<(), Error> {
+        let manifest_entries: Vec<ManifestEntry> = self
+            .find_manifest_entries_by_directory_id_and_archive_id(directory_id, source_archive_id)
+            .await?;
+        for manifest_entry in manifest_entries {
+            self.manifest_entries
+                .push((*directory_id, Some(*archive_id), manifest_entry));
+        }
+        Ok(())
+    }
+
+    async fn find_manifest_entries_by_directory_id_and_archive_id(
+        &mut self,
+        directory_id: &Uuid,
+        archive_id: Option<&Uuid>,
+    ) -> Result<Vec<ManifestEntry>, Error> {
+        let mut manifest_entries: Vec<ManifestEntry> = self
+            .manifest_entries
+            .iter()
+            .filter(|(d, a, _)| d == directory_id && a.as_ref() == archive_id)
+            .map(|(_, _, e)| e.clone())
+            .collect();
+        manifest_entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(manifest_entries)
+    }
+
+    async fn exists_manifest_entries_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .manifest_entries
+            .iter()
+            .any(|(d, a, _)| d == directory_id && a.is_none()))
+    }
+
+    async fn delete_manifest_entries_by_directory_id(
+        &mut self,
+        directory_id: &Uuid,
+    ) -> Result<(), Error> {
+        self.manifest_entries
+            .retain(|(d, a, _)| d != directory_id || a.is_some());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn get_archive(directory_id: &Uuid, name: &str, repo_id: &str, days: i64) -> Archive {
+        Archive {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            repo_id: repo_id.to_string(),
+            archive_id: name.to_string(),
+            created_date: NaiveDateTime::from_timestamp(1_600_000_000, 0) + Duration::days(days),
+            blake3_hash: String::from("hash"),
+            directory_id: Some(*directory_id),
+            fingerprint: String::from("content"),
+        }
+    }
+
+    fn get_manifest_entry(path: &str) -> ManifestEntry {
+        ManifestEntry {
+            path: path.to_string(),
+            size: 1,
+            modified_date: NaiveDateTime::from_timestamp(1_600_000_000, 0),
+            inode: 1,
+            blake3_hash: None,
+        }
+    }
+
+    async fn create_directories(catalog: &mut MemoryCatalog, paths: &[&str]) -> Vec<Uuid> {
+        catalog
+            .create_root_directory("/data", 1, "content")
+            .await
+            .unwrap();
+        let root_directory = catalog
+            .find_root_directory_by_path("/data")
+            .await
+            .unwrap()
+            .unwrap();
+        let mut ids = Vec::new();
+        for path in paths {
+            ids.push(
+                catalog
+                    .create_directory(path, "hash", "content", &root_directory.id)
+                    .await
+                    .unwrap(),
+            );
+        }
+        ids
+    }
+
+    #[async_std::test]
+    async fn directories_are_sorted_and_split_by_archives() {
+        let mut catalog = MemoryCatalog::default();
+        let ids = create_directories(&mut catalog, &["/data/b", "/data/a"]).await;
+        let paths = |directories: Vec<Directory>| -> Vec<String> {
+            directories.into_iter().map(|d| d.path).collect()
+        };
+        assert_eq!(
+            paths(catalog.find_directories().await.unwrap()),
+            vec!["/data/a", "/data/b"]
+        );
+        catalog
+            .create_archive(&get_archive(&ids[0], "b-1", "repo", 0))
+            .await
+            .unwrap();
+        assert_eq!(
+            paths(catalog.find_directories_without_archives().await.unwrap()),
+            vec!["/data/a"]
+        );
+        catalog.update_directory_dirty(&ids[1], true).await.unwrap();
+        assert_eq!(
+            paths(catalog.find_dirty_directories().await.unwrap()),
+            vec!["/data/a"]
+        );
+        catalog
+            .update_directory(&ids[1], "other", "metadata")
+            .await
+            .unwrap();
+        assert!(catalog.find_dirty_directories().await.unwrap().is_empty());
+        catalog.delete_directory(&ids[0]).await.unwrap();
+        let archives = catalog
+            .find_archives_by_directory_id(&ids[0])
+            .await
+            .unwrap();
+        assert!(archives.is_empty());
+        let archive = catalog
+            .find_archive_by_repo_id_and_archive_id("repo", "b-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(archive.directory_id, None);
+    }
+
+    #[async_std::test]
+    async fn archives_are_listed_newest_first() {
+        let mut catalog = MemoryCatalog::default();
+        let ids = create_directories(&mut catalog, &["/data/a"]).await;
+        for (name, repo_id, days) in &[("a-1", "r1", 0), ("a-3", "r1", 2), ("a-2", "r2", 1)] {
+            catalog
+                .create_archive(&get_archive(&ids[0], name, repo_id, *days))
+                .await
+                .unwrap();
+        }
+        let names: Vec<String> = catalog
+            .find_archives_by_directory_id(&ids[0])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|a| a.name)
+            .collect();
+        assert_eq!(names, vec!["a-3", "a-2", "a-1"]);
+        let latest = catalog
+            .find_latest_archive_by_directory_id_and_repo_id(&ids[0], "r2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.name, "a-2");
+        assert_eq!(
+            catalog
+                .find_repo_ids_by_directory_id(&ids[0])
+                .await
+                .unwrap(),
+            vec!["r1", "r2"]
+        );
+        assert_eq!(catalog.count_archives_by_repo_id("r1").await.unwrap(), 2);
+        let latest = catalog
+            .find_latest_archive_by_directory_id(&ids[0])
+            .await
+            .unwrap()
+            .unwrap();
+        catalog.delete_archive(&latest.id).await.unwrap();
+        let latest = catalog
+            .find_latest_archive_by_directory_id(&ids[0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.name, "a-2");
+    }
+
+    #[async_std::test]
+    async fn policies_match_their_exact_scope() {
+        let mut catalog = MemoryCatalog::default();
+        let ids = create_directories(&mut catalog, &["/data/a"]).await;
+        let policy = RetentionPolicy {
+            id: Uuid::new_v4(),
+            root_directory_id: None,
+            directory_id: Some(ids[0]),
+            keep_within: None,
+            keep_last: Some(1),
+            keep_daily: None,
+            keep_weekly: None,
+            keep_monthly: None,
+            keep_yearly: None,
+        };
+        catalog.create_retention_policy(&policy).await.unwrap();
+        assert!(catalog
+            .find_retention_policy_by_scope(None, None)
+            .await
+            .unwrap()
+            .is_none());
+        let found = catalog
+            .find_retention_policy_by_scope(None, Some(&ids[0]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.keep_last, Some(1));
+        catalog.delete_directory(&ids[0]).await.unwrap();
+        assert!(catalog
+            .find_retention_policy_by_scope(None, Some(&ids[0]))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[async_std::test]
+    async fn manifest_entries_are_copied_to_archives() {
+        let mut catalog = MemoryCatalog::default();
+        let ids = create_directories(&mut catalog, &["/data/a"]).await;
+        let archive = get_archive(&ids[0], "a-1", "repo", 0);
+        catalog.create_archive(&archive).await.unwrap();
+        catalog
+            .create_manifest_entries(&ids[0], &[get_manifest_entry("b"), get_manifest_entry("a")])
+            .await
+            .unwrap();
+        assert!(catalog
+            .exists_manifest_entries_by_directory_id(&ids[0])
+            .await
+            .unwrap());
+        catalog
+            .copy_manifest_entries(&ids[0], None, &archive.id)
+            .await
+            .unwrap();
+        catalog
+            .delete_manifest_entries_by_directory_id(&ids[0])
+            .await
+            .unwrap();
+        assert!(!catalog
+            .exists_manifest_entries_by_directory_id(&ids[0])
+            .await
+            .unwrap());
+        let paths: Vec<String> = catalog
+            .find_manifest_entries_by_directory_id_and_archive_id(&ids[0], Some(&archive.id))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(paths, vec!["a", "b"]);
+        catalog.delete_archive(&archive.id).await.unwrap();
+        assert!(catalog
+            .find_manifest_entries_by_directory_id_and_archive_id(&ids[0], Some(&archive.id))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
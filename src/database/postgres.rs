@@ -0,0 +1,51 @@
+use super::super::catalog::Catalog;
+use super::super::error::Error;
+use super::super::model::*;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::migrate::Migrator;
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+pub async fn establish_connection(url: &str) -> Result<PgConnection, Error> {
+    let mut conn = PgConnection::connect(url).await?;
+    MIGRATOR.run(&mut conn).await?;
+    Ok(conn)
+}
+
+async fn create_manifest_entries(
+    conn: &mut PgConnection,
+    directory_id: &Uuid,
+    manifest_entries: &[ManifestEntry],
+) -> Result<(), Error> {
+    let paths: Vec<&str> = manifest_entries.iter().map(|e| e.path.as_str()).collect();
+    let sizes: Vec<i64> = manifest_entries.iter().map(|e| e.size).collect();
+    let modified_dates: Vec<NaiveDateTime> =
+        manifest_entries.iter().map(|e| e.modified_date).collect();
+    let inodes: Vec<i64> = manifest_entries.iter().map(|e| e.inode).collect();
+    let blake3_hashes: Vec<String> = manifest_entries
+        .iter()
+        .map(|e| e.blake3_hash.clone().unwrap_or_default())
+        .collect();
+    sqlx::query(
+        "
+        INSERT INTO manifest_entries (directory_id, path, size, modified_date, inode, blake3_hash)
+        SELECT $1, path, size, modified_date, inode, NULLIF(blake3_hash, '')
+        FROM UNNEST($2::VARCHAR[], $3::BIGINT[], $4::TIMESTAMP[], $5::BIGINT[], $6::VARCHAR[])
+        AS t(path, size, modified_date, inode, blake3_hash)
+        ",
+    )
+    .bind(directory_id)
+    .bind(&paths)
+    .bind(&sizes)
+    .bind(&modified_dates)
+    .bind(&inodes)
+    .bind(&blake3_hashes)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+impl_catalog!(PgConnection, is_not_distinct_from = "IS NOT DISTINCT FROM");
@@ -0,0 +1,50 @@
+use super::super::catalog::Catalog;
+use super::super::error::Error;
+use super::super::model::*;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, Connection, SqliteConnection};
+use std::str::FromStr;
+use uuid::Uuid;
+
+static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+pub async fn establish_connection(url: &str) -> Result<SqliteConnection, Error> {
+    let mut conn = SqliteConnectOptions::from_str(url)?
+        .create_if_missing(true)
+        .foreign_keys(true)
+        .connect()
+        .await?;
+    MIGRATOR.run(&mut conn).await?;
+    Ok(conn)
+}
+
+async fn create_manifest_entries(
+    conn: &mut SqliteConnection,
+    directory_id: &Uuid,
+    manifest_entries: &[ManifestEntry],
+) -> Result<(), Error> {
+    let mut transaction = conn.begin().await?;
+    for manifest_entry in manifest_entries {
+        sqlx::query(
+            "
+            INSERT INTO manifest_entries (directory_id, path, size, modified_date, inode, blake3_hash)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ",
+        )
+        .bind(directory_id)
+        .bind(&manifest_entry.path)
+        .bind(manifest_entry.size)
+        .bind(manifest_entry.modified_date)
+        .bind(manifest_entry.inode)
+        .bind(&manifest_entry.blake3_hash)
+        .execute(&mut transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+impl_catalog!(SqliteConnection, is_not_distinct_from = "IS");
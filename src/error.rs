@@ -0,0 +1,62 @@
+use std::io::ErrorKind;
+use std::num::TryFromIntError;
+use thiserror::Error;
+
+pub type Failures = Vec<(String, Error)>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    InsufficientSpace(String),
+    #[error("Archiver error: {0}")]
+    Archiver(std::io::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Path {0} is not valid UTF-8")]
+    InvalidPath(String),
+    #[error("Database error: {0}")]
+    Database(sqlx::Error),
+    #[error("Migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("{0}")]
+    Hook(String),
+    #[error("{0} operation(s) failed")]
+    Batch(usize),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::NotFound(_) => 3,
+            Error::Conflict(_) => 4,
+            Error::InsufficientSpace(_) => 5,
+            Error::Archiver(_) => 6,
+            Error::Io(_) | Error::InvalidPath(_) => 7,
+            Error::Database(_) | Error::Migration(_) => 8,
+            Error::Batch(_) => 9,
+            Error::Hook(_) => 10,
+        }
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(error: TryFromIntError) -> Self {
+        Error::Io(std::io::Error::new(ErrorKind::InvalidData, error))
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => Error::NotFound(String::from("Record not found")),
+            error => Error::Database(error),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use super::exclusion::Filter;
+use async_std::task;
+use flate2::write::ZlibEncoder;
+use log::debug;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{Error, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+const SAMPLE_COUNT: u64 = 32;
+const SAMPLE_SIZE: u64 = 256 << 10;
+
+enum Algorithm {
+    None,
+    Lz4,
+    Zstd(i32),
+    Zlib(u32),
+    Lzma(u32),
+}
+
+/// Estimates the ratio of compressed to original size of the files under
+/// the filtered directory by compressing samples spread evenly over their
+/// combined bytes.
+pub async fn compression_ratio(filter: &Filter, compression: &str) -> Result<f64, Error> {
+    let algorithm = match parse_compression(compression) {
+        Some(Algorithm::None) => return Ok(1.0),
+        Some(algorithm) => algorithm,
+        None => {
+            debug!("Cannot sample compression {}, assuming none", compression);
+            return Ok(1.0);
+        }
+    };
+    let mut filter = filter.clone();
+    task::spawn_blocking(move || sample_compression_ratio(&mut filter, &algorithm)).await
+}
+
+fn sample_compression_ratio(filter: &mut Filter, algorithm: &Algorithm) -> Result<f64, Error> {
+    let mut files: Vec<(PathBuf, u64)> = Vec::new();
+    let mut total: u64 = 0;
+    let path = filter.path().to_path_buf();
+    for entry in WalkDir::new(path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !filter.is_excluded(e))
+    {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let len = entry.metadata()?.len();
+        if len > 0 {
+            files.push((entry.into_path(), total));
+            total += len;
+        }
+    }
+    if total == 0 {
+        return Ok(1.0);
+    }
+    let offsets: Vec<u64> = (0..SAMPLE_COUNT)
+        .map(|i| total * (2 * i + 1) / (2 * SAMPLE_COUNT))
+        .collect();
+    let (original, compressed) = offsets
+        .par_iter()
+        .map(|offset| {
+            let index = files.partition_point(|(_, start)| start <= offset) - 1;
+            let (file, start) = &files[index];
+            let sample = read_sample(file, offset - start)?;
+            Ok::<_, Error>((sample.len() as u64, compressed_len(&sample, algorithm)?))
+        })
+        .try_reduce(
+            || (0, 0),
+            |(a_original, a_compressed), (b_original, b_compressed)| {
+                Ok((a_original + b_original, a_compressed + b_compressed))
+            },
+        )?;
+    if original == 0 {
+        return Ok(1.0);
+    }
+    Ok(compressed as f64 / original as f64)
+}
+
+fn read_sample(path: &PathBuf, offset: u64) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    // Keep whole samples when the offset falls near the end of a file
+    file.seek(SeekFrom::Start(offset.min(len.saturating_sub(SAMPLE_SIZE))))?;
+    let mut sample = Vec::new();
+    file.take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+fn compressed_len(data: &[u8], algorithm: &Algorithm) -> Result<u64, Error> {
+    let len = match algorithm {
+        Algorithm::None => data.len(),
+        Algorithm::Lz4 => lz4_flex::compress(data).len(),
+        Algorithm::Zstd(level) => zstd::encode_all(data, *level)?.len(),
+        Algorithm::Zlib(level) => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(*level));
+            encoder.write_all(data)?;
+            encoder.finish()?.len()
+        }
+        Algorithm::Lzma(level) => {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), *level);
+            encoder.write_all(data)?;
+            encoder.finish()?.len()
+        }
+    };
+    Ok(len as u64)
+}
+
+/// Parses a borg compression spec such as `zstd,10`, `auto,lzma,6` or `obfuscate,3,lz4`,
+/// other archivers' specs such as restic's `auto` are not recognized.
+fn parse_compression(compression: &str) -> Option<Algorithm> {
+    let mut parts: Vec<&str> = compression.split(',').collect();
+    if parts.first() == Some(&"obfuscate") && parts.len() > 2 {
+        parts.drain(..2);
+    }
+    if parts.first() == Some(&"auto") {
+        parts.remove(0);
+    }
+    let level = |default| match parts.get(1) {
+        Some(level) => level.parse().ok(),
+        None => Some(default),
+    };
+    match parts.first().copied() {
+        Some("none") => Some(Algorithm::None),
+        Some("lz4") => Some(Algorithm::Lz4),
+        Some("zstd") => Some(Algorithm::Zstd(level(3)? as i32)),
+        Some("zlib") => Some(Algorithm::Zlib(level(6)?)),
+        Some("lzma") => Some(Algorithm::Lzma(level(6)?)),
+        _ => None,
+    }
+}
@@ -0,0 +1,353 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+const IGNORE_FILE: &str = ".permafrustignore";
+const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Decides which entries under a directory are left out of hashes, sizes
+/// and archives. Patterns use gitignore syntax relative to the directory,
+/// `!` re-includes, and `.permafrustignore` files found along the way apply
+/// to their own subtree and take precedence. Directories tagged with a
+/// `CACHEDIR.TAG` are always excluded.
+#[derive(Clone)]
+pub struct Filter {
+    path: PathBuf,
+    lines: Vec<String>,
+    patterns: Gitignore,
+    ignore_files: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl Filter {
+    pub fn new(path: &str, patterns: &[String]) -> Result<Filter, Error> {
+        let mut builder = GitignoreBuilder::new(path);
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        }
+        Ok(Filter {
+            path: PathBuf::from(path),
+            lines: patterns.to_vec(),
+            patterns: builder
+                .build()
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+            ignore_files: HashMap::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Meant for `filter_entry`, so that excluded directories are not descended into.
+    pub fn is_excluded(&mut self, entry: &DirEntry) -> bool {
+        if entry.depth() == 0 {
+            return false;
+        }
+        let is_dir = entry.file_type().is_dir();
+        if is_dir && is_cache_directory(entry.path()) {
+            return true;
+        }
+        for directory in entry.path().ancestors().skip(1) {
+            if let Some(ignore_file) = self.ignore_file(directory) {
+                let matched = ignore_file.matched(entry.path(), is_dir);
+                if !matched.is_none() {
+                    return matched.is_ignore();
+                }
+            }
+            if directory == self.path {
+                break;
+            }
+        }
+        self.patterns.matched(entry.path(), is_dir).is_ignore()
+    }
+
+    /// Lists the patterns in effect relative to the directory, lowest
+    /// precedence first: the given ones, then those of every `.permafrustignore`
+    /// outside excluded directories, rebased on the directory.
+    pub fn patterns(&mut self) -> Vec<String> {
+        let path = self.path.clone();
+        let mut patterns = self.lines.clone();
+        for e in WalkDir::new(&path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| !self.is_excluded(e))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && e.file_name() == IGNORE_FILE)
+        {
+            let directory = e.path().parent().unwrap().strip_prefix(&path).unwrap();
+            let content = match fs::read_to_string(e.path()) {
+                Ok(content) => content,
+                Err(error) => {
+                    warn!("Skipping {}: {}", e.path().display(), error);
+                    continue;
+                }
+            };
+            patterns.extend(
+                content
+                    .lines()
+                    .filter_map(Pattern::parse)
+                    .map(|pattern| pattern.rebase(&directory.to_string_lossy())),
+            );
+        }
+        patterns
+    }
+
+    fn ignore_file(&mut self, directory: &Path) -> Option<&Gitignore> {
+        self.ignore_files
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                let ignore_path = directory.join(IGNORE_FILE);
+                if !ignore_path.is_file() {
+                    return None;
+                }
+                let mut builder = GitignoreBuilder::new(directory);
+                if let Some(error) = builder.add(&ignore_path) {
+                    warn!("Skipping {}", error);
+                }
+                builder.build().ok()
+            })
+            .as_ref()
+    }
+}
+
+/// A gitignore line, split from its `!`, leading `/` and trailing `/` markers.
+#[derive(Debug, PartialEq)]
+pub struct Pattern {
+    pub glob: String,
+    /// Re-includes what earlier patterns excluded
+    pub negated: bool,
+    /// Matches from the directory rather than at any depth
+    pub anchored: bool,
+    pub directory_only: bool,
+}
+
+impl Pattern {
+    /// Returns `None` for blank lines and comments.
+    pub fn parse(line: &str) -> Option<Pattern> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let line = match line.strip_prefix('\\') {
+            Some(escaped) if escaped.starts_with('!') || escaped.starts_with('#') => escaped,
+            _ => line,
+        };
+        let directory_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let glob = line.trim_start_matches('/');
+        if glob.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            glob: glob.to_string(),
+            negated,
+            anchored: line.contains('/'),
+            directory_only,
+        })
+    }
+
+    /// Formats the pattern as a gitignore line of a parent directory, for
+    /// which this pattern's directory is at `directory`.
+    fn rebase(&self, directory: &str) -> String {
+        let mut line = String::new();
+        if self.negated {
+            line.push('!');
+        }
+        if !directory.is_empty() {
+            line.push('/');
+            for c in directory.chars() {
+                if matches!(c, '\\' | '*' | '?' | '[' | ']') {
+                    line.push('\\');
+                }
+                line.push(c);
+            }
+        }
+        if self.anchored || !directory.is_empty() {
+            line.push('/');
+        }
+        if !self.anchored && !directory.is_empty() {
+            line.push_str("**/");
+        }
+        line.push_str(&self.glob);
+        if self.directory_only {
+            line.push('/');
+        }
+        line
+    }
+}
+
+fn is_cache_directory(path: &Path) -> bool {
+    let mut signature = [0; CACHEDIR_SIGNATURE.len()];
+    File::open(path.join(CACHEDIR_TAG))
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok()
+        && signature == CACHEDIR_SIGNATURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn create_tree(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let path = env::temp_dir().join(format!("permafrust-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        for (file, content) in files {
+            let file = path.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        path
+    }
+
+    fn walk(filter: &mut Filter) -> Vec<String> {
+        let path = filter.path().to_path_buf();
+        WalkDir::new(&path)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| !filter.is_excluded(e))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| {
+                e.path()
+                    .strip_prefix(&path)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn is_excluded_applies_patterns_and_negations() {
+        let path = create_tree(
+            "patterns",
+            &[
+                ("a.log", b""),
+                ("keep.log", b""),
+                ("src/b.log", b""),
+                ("src/main.rs", b""),
+                ("target/debug/c", b""),
+                ("build", b""),
+            ],
+        );
+        let patterns = vec![
+            "*.log".to_string(),
+            "!keep.log".to_string(),
+            "/target".to_string(),
+            "build/".to_string(),
+        ];
+        let mut filter = Filter::new(path.to_str().unwrap(), &patterns).unwrap();
+        assert_eq!(walk(&mut filter), vec!["build", "keep.log", "src/main.rs"]);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn is_excluded_prefers_nested_ignore_files() {
+        let path = create_tree(
+            "ignore-files",
+            &[
+                ("a.tmp", b""),
+                ("sub/.permafrustignore", b"!a.tmp\nb.txt\n"),
+                ("sub/a.tmp", b""),
+                ("sub/b.txt", b""),
+                ("b.txt", b""),
+            ],
+        );
+        let mut filter = Filter::new(path.to_str().unwrap(), &["*.tmp".to_string()]).unwrap();
+        assert_eq!(
+            walk(&mut filter),
+            vec!["b.txt", "sub/.permafrustignore", "sub/a.tmp"]
+        );
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn is_excluded_skips_tagged_cache_directories() {
+        let path = create_tree(
+            "cachedir",
+            &[
+                ("cache/CACHEDIR.TAG", CACHEDIR_SIGNATURE),
+                ("cache/data", b""),
+                ("fake/CACHEDIR.TAG", b"Signature: none"),
+                ("fake/data", b""),
+            ],
+        );
+        let mut filter = Filter::new(path.to_str().unwrap(), &[]).unwrap();
+        assert_eq!(walk(&mut filter), vec!["fake/CACHEDIR.TAG", "fake/data"]);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn patterns_rebases_nested_ignore_files() {
+        let path = create_tree(
+            "rebase",
+            &[
+                (".permafrustignore", b"# comment\n*.bak\n"),
+                ("a/.permafrustignore", b"*.o\n/out/\n!keep.o\n"),
+                ("a/b[1]/.permafrustignore", b"x/y\n"),
+                ("skip/.permafrustignore", b"z\n"),
+            ],
+        );
+        let mut filter = Filter::new(path.to_str().unwrap(), &["/skip".to_string()]).unwrap();
+        assert_eq!(
+            filter.patterns(),
+            vec![
+                "/skip",
+                "*.bak",
+                "/a/**/*.o",
+                "/a/out/",
+                "!/a/**/keep.o",
+                "/a/b\\[1\\]/x/y",
+            ]
+        );
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn parse_splits_markers() {
+        assert_eq!(Pattern::parse(""), None);
+        assert_eq!(Pattern::parse("# comment"), None);
+        assert_eq!(Pattern::parse("/"), None);
+        assert_eq!(
+            Pattern::parse("!/target/ "),
+            Some(Pattern {
+                glob: "target".to_string(),
+                negated: true,
+                anchored: true,
+                directory_only: true,
+            })
+        );
+        assert_eq!(
+            Pattern::parse("\\#notes"),
+            Some(Pattern {
+                glob: "#notes".to_string(),
+                negated: false,
+                anchored: false,
+                directory_only: false,
+            })
+        );
+        assert_eq!(
+            Pattern::parse("**/cache"),
+            Some(Pattern {
+                glob: "**/cache".to_string(),
+                negated: false,
+                anchored: true,
+                directory_only: false,
+            })
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use async_std::io::Error;
+use async_std::process::{Command, Stdio};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+pub struct Filesystem {
+    pub uuid: Option<String>,
+    pub label: Option<String>,
+    pub target: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FindmntOutput {
+    pub filesystems: Vec<Filesystem>,
+}
+
+pub async fn main(path: &str) -> Result<FindmntOutput, Error> {
+    let output = Command::new("findmnt")
+        .arg("--json")
+        .arg("--output=UUID,LABEL,TARGET")
+        .arg("--target")
+        .arg(path)
+        .stderr(Stdio::inherit())
+        .output()
+        .await?;
+    let findmnt_output = serde_json::from_slice(output.stdout.as_slice())?;
+    Ok(findmnt_output)
+}
@@ -0,0 +1,243 @@
+use super::exclusion::Filter;
+use blake3::{Hash, Hasher};
+use chrono::{DateTime, Utc};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use walkdir::WalkDir;
+
+pub const METADATA: &str = "metadata";
+pub const CONTENT: &str = "content";
+pub const FINGERPRINTS: [&str; 2] = [METADATA, CONTENT];
+
+pub enum Kind {
+    Directory,
+    File,
+    Symlink(String),
+}
+
+pub struct Entry {
+    pub path: String,
+    pub kind: Kind,
+    pub mode: u32,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+    pub inode: u64,
+    pub blake3_hash: Option<Hash>,
+}
+
+impl Entry {
+    fn is_unchanged(&self, cached: &Entry) -> bool {
+        self.size == cached.size
+            && self.inode == cached.inode
+            && self.modified.timestamp() == cached.modified.timestamp()
+            && self.modified.timestamp_subsec_micros() == cached.modified.timestamp_subsec_micros()
+    }
+}
+
+pub fn walk(
+    filter: &mut Filter,
+    fingerprint: &str,
+    cache: &HashMap<String, Entry>,
+) -> io::Result<Vec<Entry>> {
+    let path = to_string(filter.path())?;
+    let mut entries: Vec<Entry> = WalkDir::new(&path)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !filter.is_excluded(e))
+        .filter_map(|v| v.ok())
+        .map(|e| {
+            let metadata = e.metadata()?;
+            let kind = if e.file_type().is_dir() {
+                Kind::Directory
+            } else if e.file_type().is_symlink() {
+                let target = fs::read_link(e.path())?;
+                Kind::Symlink(to_string(&target)?)
+            } else {
+                Kind::File
+            };
+            Ok(Entry {
+                path: to_string(e.path().strip_prefix(&path).unwrap())?,
+                kind,
+                mode: metadata.permissions().mode(),
+                size: metadata.len(),
+                modified: DateTime::<Utc>::from(metadata.modified()?),
+                inode: metadata.ino(),
+                blake3_hash: None,
+            })
+        })
+        .collect::<io::Result<Vec<Entry>>>()?;
+    if fingerprint == CONTENT {
+        entries.par_iter_mut().try_for_each(|entry| {
+            if let Kind::File = entry.kind {
+                entry.blake3_hash = match cache.get(&entry.path) {
+                    Some(cached) if cached.blake3_hash.is_some() && entry.is_unchanged(cached) => {
+                        cached.blake3_hash
+                    }
+                    _ => {
+                        let file_path = format!("{}/{}", path, entry.path);
+                        Some(compute_file_hash(&file_path)?)
+                    }
+                };
+            }
+            Ok::<(), io::Error>(())
+        })?;
+    }
+    Ok(entries)
+}
+
+pub fn compute_entries_hash(entries: &[Entry]) -> io::Result<Hash> {
+    let mut hasher = Hasher::new();
+    for entry in entries {
+        hasher.update(entry.path.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(&entry.mode.to_le_bytes());
+        match &entry.kind {
+            Kind::Directory => {
+                hasher.update(b"d");
+            }
+            Kind::Symlink(target) => {
+                hasher.update(b"l");
+                hasher.update(target.as_bytes());
+            }
+            Kind::File => {
+                hasher.update(b"f");
+                hasher.update(&entry.size.to_le_bytes());
+                match entry.blake3_hash {
+                    Some(blake3_hash) => {
+                        hasher.update(blake3_hash.as_bytes());
+                    }
+                    None => {
+                        hasher.update(&get_modified_nanos(entry)?.to_le_bytes());
+                    }
+                }
+            }
+        }
+        hasher.update(b"\0");
+    }
+    Ok(hasher.finalize())
+}
+
+/// Same as `timestamp_nanos`, which panics outside of years 1677 to 2262.
+fn get_modified_nanos(entry: &Entry) -> io::Result<i64> {
+    entry
+        .modified
+        .timestamp()
+        .checked_mul(1_000_000_000)
+        .and_then(|nanos| nanos.checked_add(i64::from(entry.modified.timestamp_subsec_nanos())))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Modification time of {} is out of range", entry.path),
+            )
+        })
+}
+
+fn to_string(path: &Path) -> io::Result<String> {
+    path.to_str().map(String::from).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Path {} is not valid UTF-8", path.to_string_lossy()),
+        )
+    })
+}
+
+fn compute_file_hash(path: &str) -> io::Result<Hash> {
+    let mut hasher = Hasher::new();
+    let mut file = File::open(path)?;
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn get_entry(path: &str, kind: Kind, blake3_hash: Option<Hash>) -> Entry {
+        Entry {
+            path: path.to_string(),
+            kind,
+            mode: 0o644,
+            size: 3,
+            modified: Utc.timestamp(1_600_000_000, 0),
+            inode: 1,
+            blake3_hash,
+        }
+    }
+
+    #[test]
+    fn compute_entries_hash_of_nothing() {
+        assert_eq!(compute_entries_hash(&[]).unwrap(), Hasher::new().finalize());
+    }
+
+    #[test]
+    fn compute_entries_hash_covers_each_field() {
+        let hash = |entry: Entry| compute_entries_hash(&[entry]).unwrap();
+        let content = Some(blake3::hash(b"abc"));
+        let base = hash(get_entry("a", Kind::File, content));
+        assert_eq!(base, hash(get_entry("a", Kind::File, content)));
+        assert_ne!(base, hash(get_entry("b", Kind::File, content)));
+        assert_ne!(base, hash(get_entry("a", Kind::File, None)));
+        assert_ne!(
+            base,
+            hash(get_entry("a", Kind::File, Some(blake3::hash(b"abd"))))
+        );
+        assert_ne!(base, hash(get_entry("a", Kind::Directory, None)));
+        assert_ne!(
+            hash(get_entry("a", Kind::Symlink("b".to_string()), None)),
+            hash(get_entry("a", Kind::Symlink("c".to_string()), None))
+        );
+        let mut entry = get_entry("a", Kind::File, content);
+        entry.mode = 0o755;
+        assert_ne!(base, hash(entry));
+        let mut entry = get_entry("a", Kind::File, content);
+        entry.size = 4;
+        assert_ne!(base, hash(entry));
+    }
+
+    #[test]
+    fn compute_entries_hash_uses_modified_without_content() {
+        let hash = |entry: Entry| compute_entries_hash(&[entry]).unwrap();
+        let content = Some(blake3::hash(b"abc"));
+        let mut entry = get_entry("a", Kind::File, content);
+        entry.modified = Utc.timestamp(1_600_000_001, 0);
+        entry.inode = 2;
+        assert_eq!(hash(get_entry("a", Kind::File, content)), hash(entry));
+        let mut entry = get_entry("a", Kind::File, None);
+        entry.modified = Utc.timestamp(1_600_000_001, 0);
+        assert_ne!(hash(get_entry("a", Kind::File, None)), hash(entry));
+    }
+
+    #[test]
+    fn compute_entries_hash_separates_entries() {
+        let entries = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|path| get_entry(path, Kind::Directory, None))
+                .collect::<Vec<Entry>>()
+        };
+        assert_ne!(
+            compute_entries_hash(&entries(&["ab"])).unwrap(),
+            compute_entries_hash(&entries(&["a", "b"])).unwrap()
+        );
+        assert_ne!(
+            compute_entries_hash(&entries(&["a", "b"])).unwrap(),
+            compute_entries_hash(&entries(&["b", "a"])).unwrap()
+        );
+    }
+
+    #[test]
+    fn compute_entries_hash_rejects_out_of_range_times() {
+        let mut entry = get_entry("a", Kind::File, None);
+        entry.modified = Utc.ymd(2263, 1, 1).and_hms(0, 0, 0);
+        assert!(compute_entries_hash(&[entry]).is_err());
+        let mut entry = get_entry("a", Kind::File, Some(blake3::hash(b"abc")));
+        entry.modified = Utc.ymd(1600, 1, 1).and_hms(0, 0, 0);
+        assert!(compute_entries_hash(&[entry]).is_ok());
+    }
+}
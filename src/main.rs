@@ -3,64 +3,343 @@ extern crate lazy_static;
 extern crate env_logger;
 extern crate log;
 
-use clap::App;
+use clap::{App, Arg, ArgMatches};
+use config::Config;
 use dotenv::dotenv;
+use error::Error;
+use log::info;
 use std::env;
 
+mod archiver;
 mod borg;
+mod catalog;
 mod cli;
+mod config;
 mod database;
-mod df;
-mod du;
+mod error;
+mod estimate;
+mod exclusion;
+mod findmnt;
+mod fingerprint;
 mod model;
 mod permafrust;
+mod progress;
+mod restic;
+mod space;
+mod tarball;
+mod watcher;
 
 #[async_std::main]
 async fn main() {
     env_logger::init();
+    // Before parsing, so that .env values apply to every option backed by the environment
+    dotenv().ok();
     let matches = App::new(env!("CARGO_BIN_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .author(env!("CARGO_PKG_AUTHORS"))
+        .arg(
+            Arg::with_name("CONFIG")
+                .long("config")
+                .help("Configuration file [default: $XDG_CONFIG_HOME/permafrust/config.toml]")
+                .required(false)
+                .takes_value(true)
+                .env("PERMAFRUST_CONFIG")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("DATABASE_URL")
+                .long("database-url")
+                .help("Catalog database URL, takes precedence over database_url in the configuration file")
+                .required(false)
+                .takes_value(true)
+                .env("DATABASE_URL")
+                .hide_env_values(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("ARCHIVER")
+                .short("A")
+                .long("archiver")
+                .help("Archiving backend")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&archiver::ARCHIVERS)
+                .env("PERMAFRUST_ARCHIVER")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("VOLUME_SIZE")
+                .long("volume-size")
+                .help("Split tar archives into volumes of this size (e.g. 700M, 4G)")
+                .required(false)
+                .takes_value(true)
+                .env("PERMAFRUST_VOLUME_SIZE")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("MARGIN")
+                .long("margin")
+                .help("Percentage of the repository filesystem to keep free after archiving")
+                .required(false)
+                .takes_value(true)
+                .env("PERMAFRUST_MARGIN")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("ESTIMATE_DRY_RUN")
+                .long("estimate-dry-run")
+                .help("Estimate the deduplicated size of archives with a dry run before creating them, for archivers reporting its statistics (restic)")
+                .required(false)
+                .global(true),
+        )
         .subcommands(vec![
             cli::watch_subcommand(),
             cli::unwatch_subcommand(),
             cli::scan_subcommand(),
+            cli::status_subcommand(),
+            cli::daemon_subcommand(),
             cli::init_subcommand(),
             cli::list_subcommand(),
             cli::create_subcommand(),
             cli::update_subcommand(),
+            cli::prune_subcommand(),
+            cli::diff_subcommand(),
+            cli::retention_subcommand(),
+            cli::replicate_subcommand(),
+            cli::replication_subcommand(),
+            cli::exclude_subcommand(),
             cli::delete_subcommand(),
             cli::extract_subcommand(),
             cli::check_subcommand(),
+            cli::transfer_subcommand(),
+            cli::reconcile_subcommand(),
+            cli::import_subcommand(),
+            cli::plan_subcommand(),
+            cli::repo_subcommand(),
+            cli::drive_subcommand(),
+            cli::where_subcommand(),
+            cli::stats_subcommand(),
         ])
         .get_matches();
 
     if matches.subcommand.is_some() {
-        dotenv().ok();
-        let pool = database::establish_connection(&env::var("DATABASE_URL").unwrap()).await;
-        match matches.subcommand_name() {
-            Some("watch") => cli::watch(&pool, matches.subcommand_matches("watch").unwrap()).await,
-            Some("unwatch") => {
-                cli::unwatch(&pool, matches.subcommand_matches("unwatch").unwrap()).await
-            }
-            Some("scan") => cli::scan(&pool, matches.subcommand_matches("scan").unwrap()).await,
-            Some("init") => cli::init(matches.subcommand_matches("init").unwrap()).await,
-            Some("list") => cli::list(&pool, matches.subcommand_matches("list").unwrap()).await,
-            Some("create") => {
-                cli::create(&pool, matches.subcommand_matches("create").unwrap()).await
-            }
-            Some("update") => {
-                cli::update(&pool, matches.subcommand_matches("update").unwrap()).await
-            }
-            Some("delete") => {
-                cli::delete(&pool, matches.subcommand_matches("delete").unwrap()).await
-            }
-            Some("extract") => {
-                cli::extract(&pool, matches.subcommand_matches("extract").unwrap()).await
-            }
-            Some("check") => cli::check(&pool, matches.subcommand_matches("check").unwrap()).await,
-            _ => (),
+        if let Err(error) = run(&matches).await {
+            eprintln!("error: {}", error);
+            std::process::exit(error.exit_code());
         }
     }
 }
+
+async fn run(matches: &ArgMatches<'_>) -> Result<(), Error> {
+    let config = &Config::load(matches.value_of("CONFIG"))?;
+    let database_url = match (matches.value_of("DATABASE_URL"), &config.database_url) {
+        (Some(database_url), _) => {
+            info!("Using the catalog from --database-url or DATABASE_URL");
+            database_url
+        }
+        (None, Some(database_url)) => {
+            info!("Using the catalog from the configuration file");
+            database_url
+        }
+        (None, None) => return Err(Error::Config(String::from("DATABASE_URL is not set"))),
+    };
+    let mut catalog = database::establish_connection(database_url).await?;
+    match matches.subcommand_name() {
+        Some("watch") => {
+            cli::watch(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("watch").unwrap(),
+            )
+            .await
+        }
+        Some("unwatch") => {
+            cli::unwatch(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("unwatch").unwrap(),
+            )
+            .await
+        }
+        Some("scan") => {
+            cli::scan(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("scan").unwrap(),
+            )
+            .await
+        }
+        Some("status") => cli::status(catalog.as_mut(), config).await,
+        Some("daemon") => {
+            cli::daemon(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("daemon").unwrap(),
+            )
+            .await
+        }
+        Some("init") => cli::init(config, matches.subcommand_matches("init").unwrap()).await,
+        Some("list") => {
+            cli::list(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("list").unwrap(),
+            )
+            .await
+        }
+        Some("create") => {
+            cli::create(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("create").unwrap(),
+            )
+            .await
+        }
+        Some("update") => {
+            cli::update(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("update").unwrap(),
+            )
+            .await
+        }
+        Some("prune") => {
+            cli::prune(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("prune").unwrap(),
+            )
+            .await
+        }
+        Some("diff") => {
+            cli::diff(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("diff").unwrap(),
+            )
+            .await
+        }
+        Some("retention") => {
+            cli::retention(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("retention").unwrap(),
+            )
+            .await
+        }
+        Some("replicate") => {
+            cli::replicate(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("replicate").unwrap(),
+            )
+            .await
+        }
+        Some("replication") => {
+            cli::replication(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("replication").unwrap(),
+            )
+            .await
+        }
+        Some("exclude") => {
+            cli::exclude(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("exclude").unwrap(),
+            )
+            .await
+        }
+        Some("delete") => {
+            cli::delete(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("delete").unwrap(),
+            )
+            .await
+        }
+        Some("extract") => {
+            cli::extract(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("extract").unwrap(),
+            )
+            .await
+        }
+        Some("check") => {
+            cli::check(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("check").unwrap(),
+            )
+            .await
+        }
+        Some("transfer") => {
+            cli::transfer(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("transfer").unwrap(),
+            )
+            .await
+        }
+        Some("reconcile") => {
+            cli::reconcile(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("reconcile").unwrap(),
+            )
+            .await
+        }
+        Some("import") => {
+            cli::import(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("import").unwrap(),
+            )
+            .await
+        }
+        Some("plan") => {
+            cli::plan(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("plan").unwrap(),
+            )
+            .await
+        }
+        Some("repo") => {
+            cli::repo(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("repo").unwrap(),
+            )
+            .await
+        }
+        Some("drive") => {
+            cli::drive(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("drive").unwrap(),
+            )
+            .await
+        }
+        Some("where") => {
+            cli::locate(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("where").unwrap(),
+            )
+            .await
+        }
+        Some("stats") => {
+            cli::stats(
+                catalog.as_mut(),
+                config,
+                matches.subcommand_matches("stats").unwrap(),
+            )
+            .await
+        }
+        _ => Ok(()),
+    }
+}
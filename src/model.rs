@@ -3,22 +3,25 @@ use sqlx::types::chrono::NaiveDateTime;
 use sqlx::FromRow;
 use uuid::Uuid;
 
-#[derive(FromRow, Serialize)]
+#[derive(Clone, FromRow, Serialize)]
 pub struct RootDirectory {
     pub id: Uuid,
     pub path: String,
     pub depth: i16,
+    pub fingerprint: String,
 }
 
-#[derive(FromRow, Serialize)]
+#[derive(Clone, FromRow, Serialize)]
 pub struct Directory {
     pub id: Uuid,
     pub path: String,
     pub blake3_hash: String,
     pub root_directory_id: Uuid,
+    pub fingerprint: String,
+    pub dirty: bool,
 }
 
-#[derive(FromRow)]
+#[derive(Clone, FromRow)]
 pub struct Archive {
     pub id: Uuid,
     pub name: String,
@@ -27,4 +30,74 @@ pub struct Archive {
     pub created_date: NaiveDateTime,
     pub blake3_hash: String,
     pub directory_id: Option<Uuid>,
+    pub fingerprint: String,
+}
+
+#[derive(Clone, FromRow)]
+pub struct ArchiveStats {
+    pub archive_id: Uuid,
+    pub original_size: i64,
+    pub compressed_size: i64,
+    pub deduplicated_size: i64,
+    pub nfiles: i64,
+    pub duration: f64,
+}
+
+#[derive(Clone, FromRow)]
+pub struct RetentionPolicy {
+    pub id: Uuid,
+    pub root_directory_id: Option<Uuid>,
+    pub directory_id: Option<Uuid>,
+    pub keep_within: Option<String>,
+    pub keep_last: Option<i32>,
+    pub keep_daily: Option<i32>,
+    pub keep_weekly: Option<i32>,
+    pub keep_monthly: Option<i32>,
+    pub keep_yearly: Option<i32>,
+}
+
+#[derive(Clone, FromRow)]
+pub struct Repository {
+    pub id: Uuid,
+    pub label: String,
+    pub location: String,
+    pub repo_id: String,
+    pub encryption: String,
+    pub last_seen_date: NaiveDateTime,
+    pub drive_id: Option<Uuid>,
+}
+
+#[derive(Clone, FromRow)]
+pub struct Drive {
+    pub id: Uuid,
+    pub uuid: String,
+    pub label: Option<String>,
+    pub size: i64,
+    pub avail: i64,
+    pub last_mounted_date: NaiveDateTime,
+}
+
+#[derive(Clone, FromRow)]
+pub struct ReplicationPolicy {
+    pub id: Uuid,
+    pub root_directory_id: Option<Uuid>,
+    pub directory_id: Option<Uuid>,
+    pub copies: i16,
+}
+
+#[derive(Clone, FromRow)]
+pub struct ExclusionPolicy {
+    pub id: Uuid,
+    pub root_directory_id: Option<Uuid>,
+    pub directory_id: Option<Uuid>,
+    pub patterns: String,
+}
+
+#[derive(Clone, FromRow)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: i64,
+    pub modified_date: NaiveDateTime,
+    pub inode: i64,
+    pub blake3_hash: Option<String>,
 }
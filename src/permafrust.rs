@@ -1,260 +1,2835 @@
-use super::borg;
-use super::database::*;
-use super::df;
-use super::du;
+use super::archiver::{self, Archiver, Stats};
+use super::catalog::Catalog;
+use super::config::Config;
+use super::error::{Error, Failures};
+use super::estimate;
+use super::exclusion::Filter;
+use super::findmnt;
+use super::fingerprint;
 use super::model::*;
+use super::progress;
+use super::space;
+use super::watcher::Watcher;
 use any_ascii::any_ascii;
+use async_std::fs;
+use async_std::io::ErrorKind;
 use async_std::path::Path;
-use blake3::{Hash, Hasher};
-use chrono::{DateTime, Local};
-use log::info;
-use sqlx::PgConnection;
+use async_std::process::Command;
+use async_std::task;
+use blake3::Hash;
+use chrono::{DateTime, Local, Timelike, Utc};
+use indicatif::{HumanBytes, ProgressBar};
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
-use walkdir::{DirEntry, WalkDir};
+use walkdir::WalkDir;
 
-pub async fn watch(conn: &mut PgConnection, path: &str, depth: i16) {
-    let path = Path::new(path)
-        .canonicalize()
-        .await
-        .expect("Failed to canonicalize path");
-    let path = path.as_os_str().to_str().unwrap();
-    let directory = find_root_directory_by_path(conn, path).await;
+const UNKNOWN_HASH: &str = "unknown";
+
+struct Bin {
+    repository: Repository,
+    label: String,
+    size: i64,
+    avail: i64,
+    mounted: bool,
+    directories: Vec<(Directory, i64)>,
+}
+
+impl Bin {
+    fn fits(&self, size: i64, margin: f64) -> bool {
+        let used: i64 = self.directories.iter().map(|(_, size)| size).sum();
+        (self.avail - used - size) as f64 / self.size as f64 >= margin
+    }
+}
+
+pub async fn watch(
+    catalog: &mut dyn Catalog,
+    path: &str,
+    depth: i16,
+    fingerprint: &str,
+) -> Result<(), Error> {
+    let path = Path::new(path).canonicalize().await?;
+    let path = to_str(path.as_os_str())?;
+    let directory = catalog.find_root_directory_by_path(path).await?;
     match directory {
-        Some(_) => println!("Directory {} is already watched", path),
-        None => create_root_directory(conn, path, depth).await,
+        Some(directory) => {
+            if directory.fingerprint != fingerprint {
+                catalog
+                    .update_root_directory(&directory.id, fingerprint)
+                    .await?;
+            } else {
+                println!("Directory {} is already watched", path);
+            }
+        }
+        None => {
+            catalog
+                .create_root_directory(path, depth, fingerprint)
+                .await?
+        }
     }
+    Ok(())
 }
 
-pub async fn unwatch(conn: &mut PgConnection, path: &str) {
-    let path = Path::new(path)
-        .canonicalize()
-        .await
-        .expect("Failed to canonicalize path");
-    let path = path.as_os_str().to_str().unwrap();
-    let directory = find_root_directory_by_path(conn, path).await;
+pub async fn unwatch(catalog: &mut dyn Catalog, path: &str) -> Result<(), Error> {
+    let path = Path::new(path).canonicalize().await?;
+    let path = to_str(path.as_os_str())?;
+    let directory = catalog.find_root_directory_by_path(path).await?;
     match directory {
-        Some(directory) => delete_root_directory(conn, &directory.id).await,
+        Some(directory) => catalog.delete_root_directory(&directory.id).await?,
         None => println!("Directory does not exist"),
     }
+    Ok(())
 }
 
-pub async fn scan(conn: &mut PgConnection) {
-    let root_directories = find_root_directories(conn).await;
-    for root_directory in root_directories {
-        let directories: Vec<DirEntry> = WalkDir::new(&root_directory.path)
-            .into_iter()
-            .filter_entry(|e| {
-                e.depth() <= usize::try_from(root_directory.depth).unwrap()
-                    && e.path().is_dir()
-                    && !e.file_name().to_str().unwrap().starts_with(".")
-            })
-            .filter_map(|v| v.ok())
-            .filter(|e| e.depth() == usize::try_from(root_directory.depth).unwrap())
-            .collect();
-        for directory in directories {
-            let path = directory.path().as_os_str().to_str().unwrap();
-            let blake3_hash = compute_directory_hash(path);
-            let directory = find_directory_by_path(conn, path).await;
-            match directory {
-                Some(directory) => {
-                    if blake3_hash != Hash::from_hex(directory.blake3_hash).unwrap() {
-                        update_directory(conn, &directory.id, &blake3_hash.to_hex()).await;
-                    }
-                }
-                None => {
-                    create_directory(conn, path, &blake3_hash.to_hex(), &root_directory.id).await;
-                }
-            }
-        }
+pub async fn scan(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    incremental: bool,
+) -> Result<(), Error> {
+    let root_directories = catalog.find_root_directories().await?;
+    for root_directory in &root_directories {
+        scan_root_directory(catalog, config, root_directory, incremental).await?;
     }
-    let directories = find_directories(conn).await;
-    for directory in directories {
-        let path = Path::new(&directory.path);
-        if !path.is_dir().await {
-            delete_directory(conn, &directory.id).await;
+    remove_missing_directories(catalog).await?;
+    report(catalog).await?;
+    Ok(())
+}
+
+pub async fn status(catalog: &mut dyn Catalog) -> Result<(), Error> {
+    report(catalog).await?;
+    Ok(())
+}
+
+pub async fn daemon(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    debounce: u64,
+    interval: u64,
+) -> Result<(), Error> {
+    let debounce = Duration::from_secs(debounce);
+    let interval = Duration::from_secs(interval);
+    let mut watcher = Watcher::new()?;
+    let mut watched_root_directory_ids: HashSet<Uuid> = HashSet::new();
+    let mut root_directories = catalog.find_root_directories().await?;
+    for root_directory in &root_directories {
+        watcher.add_recursive(std::path::Path::new(&root_directory.path));
+        watched_root_directory_ids.insert(root_directory.id);
+        scan_root_directory(catalog, config, root_directory, true).await?;
+    }
+    remove_missing_directories(catalog).await?;
+    info!("Watching {} root directories", root_directories.len());
+    let mut stale_root_directory_ids: HashSet<Uuid> = HashSet::new();
+    let mut overflowed = false;
+    let mut last_event: Option<Instant> = None;
+    let mut last_refresh = Instant::now();
+    loop {
+        let changes = watcher.read()?;
+        if changes.overflowed && !overflowed {
+            warn!("Missed file system events, rescanning every root directory");
+            overflowed = true;
         }
-        let archive = find_archive_by_directory_id(conn, &directory.id).await;
-        match archive {
-            Some(archive) => {
-                if archive.blake3_hash != directory.blake3_hash {
-                    println!(
-                        "Out of date: {} [{}:{}]",
-                        &directory.path, &directory.root_directory_id, &directory.id
-                    );
+        if changes.overflowed || !changes.paths.is_empty() {
+            // Keep status current, only rehashing waits for events to settle
+            stale_root_directory_ids.extend(
+                mark_dirty_directories(catalog, &root_directories, changes.paths.into_iter())
+                    .await?,
+            );
+            last_event = Some(Instant::now());
+        }
+        let settled = matches!(last_event, Some(last_event) if last_event.elapsed() >= debounce);
+        if settled || last_refresh.elapsed() >= interval {
+            root_directories = catalog.find_root_directories().await?;
+            for root_directory in &root_directories {
+                if watched_root_directory_ids.insert(root_directory.id) {
+                    watcher.add_recursive(std::path::Path::new(&root_directory.path));
+                    scan_root_directory(catalog, config, root_directory, true).await?;
+                } else if overflowed {
+                    // Directories created meanwhile went unwatched
+                    info!("Rescanning {}", &root_directory.path);
+                    watcher.add_recursive(std::path::Path::new(&root_directory.path));
+                    scan_root_directory(catalog, config, root_directory, true).await?;
+                } else if stale_root_directory_ids.contains(&root_directory.id) {
+                    info!("Rescanning {}", &root_directory.path);
+                    scan_root_directory(catalog, config, root_directory, true).await?;
                 }
             }
-            None => println!(
-                "Not backed up: {} [{}:{}]",
-                &directory.path, &directory.root_directory_id, &directory.id
-            ),
+            overflowed = false;
+            stale_root_directory_ids.clear();
+            let dirty_directories = catalog.find_dirty_directories().await?;
+            for root_directory in &root_directories {
+                let mut paths: Vec<String> = Vec::new();
+                for directory in &dirty_directories {
+                    if directory.root_directory_id != root_directory.id {
+                        continue;
+                    }
+                    if Path::new(&directory.path).is_dir().await {
+                        info!("Rehashing {}", &directory.path);
+                        paths.push(directory.path.clone());
+                    } else {
+                        catalog.delete_directory(&directory.id).await?;
+                    }
+                }
+                hash_directories(catalog, config, root_directory, &paths, true).await?;
+            }
+            last_event = None;
+            last_refresh = Instant::now();
         }
+        task::sleep(Duration::from_millis(500)).await;
     }
 }
 
-pub async fn init(repo: &str, encryption: &str) {
-    borg::init(repo, encryption)
+pub async fn init(archiver: &dyn Archiver, repo: &str, encryption: &str) -> Result<(), Error> {
+    archiver
+        .init(repo, encryption)
         .await
-        .expect("Failed to init repo");
+        .map_err(Error::Archiver)?;
+    Ok(())
 }
 
-pub async fn list(conn: &mut PgConnection, repo: &str) {
-    let list_output = borg::list(repo).await.expect("Failed to list repo");
-    for archive in list_output.archives {
-        let archive =
-            find_archive_by_repo_id_and_archive_id(conn, &list_output.repository.id, &archive.id)
-                .await
-                .unwrap();
-        println!("{} {} [{}]", archive.name, archive.created_date, archive.id);
+pub async fn list(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    directory_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    match directory_id {
+        Some(directory_id) => {
+            let directory = catalog.find_directory_by_id(directory_id).await?;
+            println!("{}", directory.path);
+            for archive in catalog.find_archives_by_directory_id(&directory.id).await? {
+                let archive_stats = catalog
+                    .find_archive_stats_by_archive_id(&archive.id)
+                    .await?;
+                println!(
+                    "{} {} {} [{}]{}",
+                    archive.name,
+                    archive.created_date,
+                    archive.archive_id,
+                    archive.id,
+                    format_optional_archive_stats(archive_stats.as_ref())
+                );
+            }
+        }
+        None => {
+            let repo = find_repo_location(
+                catalog,
+                repo.ok_or_else(|| Error::Config(String::from("No repository specified")))?,
+            )
+            .await?;
+            let list_output = archiver.list(&repo).await.map_err(Error::Archiver)?;
+            for archive in list_output.archives {
+                let archive = match catalog
+                    .find_archive_by_repo_id_and_archive_id(&list_output.repository.id, &archive.id)
+                    .await?
+                {
+                    Some(archive) => archive,
+                    None => {
+                        println!("{} {} (not in catalog)", archive.name, archive.start);
+                        continue;
+                    }
+                };
+                let archive_stats = catalog
+                    .find_archive_stats_by_archive_id(&archive.id)
+                    .await?;
+                println!(
+                    "{} {} [{}]{}",
+                    archive.name,
+                    archive.created_date,
+                    archive.id,
+                    format_optional_archive_stats(archive_stats.as_ref())
+                );
+            }
+        }
     }
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create(
-    conn: &mut PgConnection,
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
     repo: &str,
     directory_id: &Uuid,
-    compression: &str,
+    config: &Config,
     dry_run: bool,
     root_directories: bool,
-) {
+) -> Result<Failures, Error> {
     let directories = if root_directories {
-        find_directories_without_archives_by_root_directory_id(conn, directory_id).await
+        catalog
+            .find_directories_without_archives_by_root_directory_id(directory_id)
+            .await?
     } else {
-        vec![find_directory_by_id(conn, directory_id).await]
+        vec![catalog.find_directory_by_id(directory_id).await?]
     };
+    let repo = &find_repo_location(catalog, repo).await?;
+    let repo_id = archiver
+        .info(repo)
+        .await
+        .map_err(Error::Archiver)?
+        .repository
+        .id;
+    let progress_bar = progress::batch(directories.len());
+    let mut failures: Failures = Vec::new();
     for directory in directories {
-        if let Some(archive) = find_archive_by_directory_id(conn, &directory.id).await {
-            panic!("Archive {} already exists", &archive.name);
-        };
-        let df_output = df::main(repo).await;
-        let du_output = du::main(&directory.path).await;
-        let remaining_space_after =
-            f64::from(df_output.avail - du_output.size) / f64::from(df_output.size);
-        info!("Remaining space after: {}", remaining_space_after);
-        if remaining_space_after < 0.05 {
-            panic!("Not enough space");
-        }
-        let prefix = get_archive_prefix(&directory.path);
-        let create_output = borg::create(repo, &prefix, &directory.path, compression, dry_run)
-            .await
-            .expect("Failed to create archive");
-        create_archive(
-            conn,
-            &prefix,
-            &create_output.repository.id,
-            &create_output.archive.id,
-            &Local::now().naive_local(),
-            &directory.blake3_hash,
-            &directory.id,
+        if let Err(error) = create_directory(
+            catalog, archiver, repo, &repo_id, &directory, config, dry_run,
         )
-        .await;
+        .await
+        {
+            failures.push((directory.path, error));
+        }
+        progress_bar.inc(1);
     }
+    Ok(failures)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn update(
-    conn: &mut PgConnection,
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    directory_id: &Uuid,
+    config: &Config,
+    dry_run: bool,
+    root_directories: bool,
+) -> Result<Failures, Error> {
+    let directories = if root_directories {
+        catalog
+            .find_directories_with_archives_by_root_directory_id(directory_id)
+            .await?
+    } else {
+        vec![catalog.find_directory_by_id(directory_id).await?]
+    };
+    let progress_bar = progress::batch(directories.len());
+    let mut failures: Failures = Vec::new();
+    for directory in directories {
+        if let Err(error) =
+            update_directory(catalog, archiver, repo, &directory, config, dry_run).await
+        {
+            failures.push((directory.path, error));
+        }
+        progress_bar.inc(1);
+    }
+    Ok(failures)
+}
+
+pub async fn prune(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    directory_id: &Uuid,
+    config: &Config,
+    dry_run: bool,
+    root_directories: bool,
+) -> Result<Failures, Error> {
+    let directories = if root_directories {
+        catalog
+            .find_directories_with_archives_by_root_directory_id(directory_id)
+            .await?
+    } else {
+        vec![catalog.find_directory_by_id(directory_id).await?]
+    };
+    let mut failures: Failures = Vec::new();
+    for directory in directories {
+        if let Err(error) =
+            prune_archived_directory(catalog, archiver, repo, &directory, config, dry_run).await
+        {
+            failures.push((directory.path, error));
+        }
+    }
+    Ok(failures)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn replicate(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
     repo: &str,
     directory_id: &Uuid,
-    compression: &str,
+    config: &Config,
+    transfer: bool,
     dry_run: bool,
     root_directories: bool,
-) {
+) -> Result<Failures, Error> {
     let directories = if root_directories {
-        find_directories_with_archives_by_root_directory_id(conn, directory_id).await
+        catalog
+            .find_directories_with_archives_by_root_directory_id(directory_id)
+            .await?
     } else {
-        vec![find_directory_by_id(conn, directory_id).await]
+        vec![catalog.find_directory_by_id(directory_id).await?]
     };
+    let repo = &find_repo_location(catalog, repo).await?;
+    let repo_id = archiver
+        .info(repo)
+        .await
+        .map_err(Error::Archiver)?
+        .repository
+        .id;
+    let mut failures: Failures = Vec::new();
     for directory in directories {
-        let archive = find_archive_by_directory_id(conn, &directory.id)
-            .await
-            .unwrap();
-        check_archive_repository(&archive, repo).await;
-        let df_output = df::main(repo).await;
-        let du_output = du::main(&directory.path).await;
-        let remaining_space_after =
-            f64::from(df_output.avail - du_output.size) / f64::from(df_output.size);
-        info!("Remaining space after: {}", remaining_space_after);
-        if remaining_space_after < 0.05 {
-            panic!("Not enough space");
+        if let Err(error) = replicate_directory(
+            catalog, archiver, repo, &repo_id, &directory, config, transfer, dry_run,
+        )
+        .await
+        {
+            failures.push((directory.path, error));
         }
-        let prefix = get_archive_prefix(&directory.path);
-        let create_output = borg::create(repo, &prefix, &directory.path, compression, dry_run)
-            .await
-            .expect("Failed to create new archive");
-        borg::prune(repo, &prefix, dry_run)
-            .await
-            .expect("Failed to prune old archive(s)");
-        update_archive(
-            conn,
-            &archive.id,
-            &create_output.archive.id,
-            &Local::now().naive_local(),
-            &directory.blake3_hash,
+    }
+    Ok(failures)
+}
+
+pub async fn show_replication_policy(
+    catalog: &mut dyn Catalog,
+    root_directory_id: Option<&Uuid>,
+    directory_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    match catalog
+        .find_replication_policy_by_scope(root_directory_id, directory_id)
+        .await?
+    {
+        Some(replication_policy) => println!("copies: {}", replication_policy.copies),
+        None => println!("No replication policy"),
+    }
+    Ok(())
+}
+
+pub async fn set_replication_policy(
+    catalog: &mut dyn Catalog,
+    replication_policy: &ReplicationPolicy,
+) -> Result<(), Error> {
+    if let Some(existing_replication_policy) = catalog
+        .find_replication_policy_by_scope(
+            replication_policy.root_directory_id.as_ref(),
+            replication_policy.directory_id.as_ref(),
         )
-        .await;
+        .await?
+    {
+        catalog
+            .delete_replication_policy(&existing_replication_policy.id)
+            .await?;
+    }
+    catalog
+        .create_replication_policy(replication_policy)
+        .await?;
+    Ok(())
+}
+
+pub async fn unset_replication_policy(
+    catalog: &mut dyn Catalog,
+    root_directory_id: Option<&Uuid>,
+    directory_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    match catalog
+        .find_replication_policy_by_scope(root_directory_id, directory_id)
+        .await?
+    {
+        Some(replication_policy) => {
+            catalog
+                .delete_replication_policy(&replication_policy.id)
+                .await?
+        }
+        None => println!("No replication policy"),
+    }
+    Ok(())
+}
+
+pub async fn show_exclusion_policy(
+    catalog: &mut dyn Catalog,
+    root_directory_id: Option<&Uuid>,
+    directory_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    match catalog
+        .find_exclusion_policy_by_scope(root_directory_id, directory_id)
+        .await?
+    {
+        Some(exclusion_policy) => println!("{}", exclusion_policy.patterns),
+        None => println!("No exclusion patterns"),
+    }
+    Ok(())
+}
+
+pub async fn set_exclusion_policy(
+    catalog: &mut dyn Catalog,
+    exclusion_policy: &ExclusionPolicy,
+) -> Result<(), Error> {
+    let patterns: Vec<String> = exclusion_policy
+        .patterns
+        .lines()
+        .map(String::from)
+        .collect();
+    Filter::new("/", &patterns)
+        .map_err(|error| Error::Config(format!("Invalid exclude: {}", error)))?;
+    if let Some(existing_exclusion_policy) = catalog
+        .find_exclusion_policy_by_scope(
+            exclusion_policy.root_directory_id.as_ref(),
+            exclusion_policy.directory_id.as_ref(),
+        )
+        .await?
+    {
+        catalog
+            .delete_exclusion_policy(&existing_exclusion_policy.id)
+            .await?;
+    }
+    catalog.create_exclusion_policy(exclusion_policy).await?;
+    Ok(())
+}
+
+pub async fn unset_exclusion_policy(
+    catalog: &mut dyn Catalog,
+    root_directory_id: Option<&Uuid>,
+    directory_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    match catalog
+        .find_exclusion_policy_by_scope(root_directory_id, directory_id)
+        .await?
+    {
+        Some(exclusion_policy) => {
+            catalog
+                .delete_exclusion_policy(&exclusion_policy.id)
+                .await?
+        }
+        None => println!("No exclusion patterns"),
+    }
+    Ok(())
+}
+
+pub async fn diff(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    directory_id: &Uuid,
+    archive_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    let directory = catalog.find_directory_by_id(directory_id).await?;
+    let archive = match archive_id {
+        Some(archive_id) => catalog.find_archive_by_id(archive_id).await?,
+        None => catalog
+            .find_latest_archive_by_directory_id(&directory.id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("No archive for {}", directory.path)))?,
+    };
+    let archived_entries = catalog
+        .find_manifest_entries_by_directory_id_and_archive_id(&directory.id, Some(&archive.id))
+        .await?;
+    if archived_entries.is_empty() {
+        println!("No manifest for archive {}", archive.name);
+        return Ok(());
+    }
+    let mut filter = find_exclusion_filter(catalog, config, &directory, &directory.path).await?;
+    let current_entries = get_manifest_entries(&fingerprint::walk(
+        &mut filter,
+        &directory.fingerprint,
+        &HashMap::new(),
+    )?)?;
+    let archived_entries: HashMap<&str, &ManifestEntry> = archived_entries
+        .iter()
+        .map(|e| (e.path.as_str(), e))
+        .collect();
+    for current_entry in &current_entries {
+        match archived_entries.get(current_entry.path.as_str()) {
+            Some(archived_entry) => {
+                let modified = match (&current_entry.blake3_hash, &archived_entry.blake3_hash) {
+                    (Some(current_hash), Some(archived_hash)) => current_hash != archived_hash,
+                    _ => current_entry.modified_date != archived_entry.modified_date,
+                };
+                if modified || current_entry.size != archived_entry.size {
+                    println!("Modified: {}", current_entry.path);
+                }
+            }
+            None => println!("Added: {}", current_entry.path),
+        }
+    }
+    let current_paths: HashSet<&str> = current_entries.iter().map(|e| e.path.as_str()).collect();
+    let mut removed_paths: Vec<&&str> = archived_entries
+        .keys()
+        .filter(|path| !current_paths.contains(*path))
+        .collect();
+    removed_paths.sort();
+    for path in removed_paths {
+        println!("Removed: {}", path);
+    }
+    Ok(())
+}
+
+pub async fn show_retention_policy(
+    catalog: &mut dyn Catalog,
+    root_directory_id: Option<&Uuid>,
+    directory_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    match catalog
+        .find_retention_policy_by_scope(root_directory_id, directory_id)
+        .await?
+    {
+        Some(retention_policy) => {
+            if let Some(keep_within) = retention_policy.keep_within {
+                println!("keep-within: {}", keep_within);
+            }
+            if let Some(keep_last) = retention_policy.keep_last {
+                println!("keep-last: {}", keep_last);
+            }
+            if let Some(keep_daily) = retention_policy.keep_daily {
+                println!("keep-daily: {}", keep_daily);
+            }
+            if let Some(keep_weekly) = retention_policy.keep_weekly {
+                println!("keep-weekly: {}", keep_weekly);
+            }
+            if let Some(keep_monthly) = retention_policy.keep_monthly {
+                println!("keep-monthly: {}", keep_monthly);
+            }
+            if let Some(keep_yearly) = retention_policy.keep_yearly {
+                println!("keep-yearly: {}", keep_yearly);
+            }
+        }
+        None => println!("No retention policy"),
+    }
+    Ok(())
+}
+
+pub async fn set_retention_policy(
+    catalog: &mut dyn Catalog,
+    retention_policy: &RetentionPolicy,
+) -> Result<(), Error> {
+    if let Some(existing_retention_policy) = catalog
+        .find_retention_policy_by_scope(
+            retention_policy.root_directory_id.as_ref(),
+            retention_policy.directory_id.as_ref(),
+        )
+        .await?
+    {
+        catalog
+            .delete_retention_policy(&existing_retention_policy.id)
+            .await?;
+    }
+    catalog.create_retention_policy(retention_policy).await?;
+    Ok(())
+}
+
+pub async fn unset_retention_policy(
+    catalog: &mut dyn Catalog,
+    root_directory_id: Option<&Uuid>,
+    directory_id: Option<&Uuid>,
+) -> Result<(), Error> {
+    match catalog
+        .find_retention_policy_by_scope(root_directory_id, directory_id)
+        .await?
+    {
+        Some(retention_policy) => {
+            catalog
+                .delete_retention_policy(&retention_policy.id)
+                .await?
+        }
+        None => println!("No retention policy"),
     }
+    Ok(())
 }
 
-pub async fn delete(conn: &mut PgConnection, repo: &str, archive_id: &Uuid, dry_run: bool) {
-    let archive = find_archive_by_id(conn, archive_id).await;
-    check_archive_repository(&archive, repo).await;
-    borg::delete(repo, &archive.name, dry_run)
+pub async fn delete(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    archive_id: &Uuid,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let archive = catalog.find_archive_by_id(archive_id).await?;
+    let repo = &find_archive_repo_location(catalog, repo, &archive).await?;
+    check_archive_repository(catalog, archiver, &archive, repo).await?;
+    archiver
+        .delete(repo, &archive.name, dry_run)
         .await
-        .expect("Failed to delete archive");
-    delete_archive(conn, &archive.id).await;
+        .map_err(Error::Archiver)?;
+    if !dry_run {
+        catalog.delete_archive(&archive.id).await?;
+    }
+    Ok(())
 }
 
-pub async fn extract(conn: &mut PgConnection, repo: &str, archive_id: &Uuid, dry_run: bool) {
-    let archive = find_archive_by_id(conn, archive_id).await;
-    check_archive_repository(&archive, repo).await;
-    let directory = find_directory_by_id(conn, &archive.directory_id.unwrap()).await;
-    borg::check(repo, &archive.name, false)
+pub async fn extract(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    archive_id: &Uuid,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let archive = catalog.find_archive_by_id(archive_id).await?;
+    let repo = &find_archive_repo_location(catalog, repo, &archive).await?;
+    check_archive_repository(catalog, archiver, &archive, repo).await?;
+    let directory = catalog
+        .find_directory_by_id(&archive.directory_id.ok_or_else(|| {
+            Error::NotFound(format!("Directory of archive {} is gone", archive.name))
+        })?)
+        .await?;
+    archiver
+        .check(repo, &archive.name, false)
         .await
-        .expect("Failed to check archive");
-    borg::extract(repo, &archive.name, &directory.path, dry_run)
+        .map_err(Error::Archiver)?;
+    archiver
+        .extract(repo, &archive.name, &directory.path, dry_run)
         .await
-        .expect("Failed to extract archive");
+        .map_err(Error::Archiver)?;
+    Ok(())
 }
 
-pub async fn check(conn: &mut PgConnection, repo: &str, archive_id: &Uuid, repair: bool) {
-    let archive = find_archive_by_id(conn, archive_id).await;
-    borg::check(repo, &archive.name, repair)
+pub async fn check(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    archive_id: &Uuid,
+    repair: bool,
+) -> Result<(), Error> {
+    let archive = catalog.find_archive_by_id(archive_id).await?;
+    let repo = &find_archive_repo_location(catalog, repo, &archive).await?;
+    check_archive_repository(catalog, archiver, &archive, repo).await?;
+    archiver
+        .check(repo, &archive.name, repair)
         .await
-        .expect("Failed to check archive");
+        .map_err(Error::Archiver)?;
+    Ok(())
 }
 
-async fn check_archive_repository(archive: &Archive, repo: &str) {
-    let repo_id = borg::info(repo).await.unwrap().repository.id;
-    if archive.repo_id != repo_id {
-        panic!("Wrong repository!");
+pub async fn transfer(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: &str,
+    other_repo: &str,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let other_repository = find_repository(catalog, other_repo).await?;
+    let repo = &find_repo_location(catalog, repo).await?;
+    archiver
+        .transfer(repo, &other_repository.location, None, dry_run)
+        .await
+        .map_err(Error::Archiver)?;
+    if dry_run {
+        return Ok(());
+    }
+    let list_output = archiver.list(repo).await.map_err(Error::Archiver)?;
+    for directory in catalog.find_directories().await? {
+        for archive in catalog.find_archives_by_directory_id(&directory.id).await? {
+            if archive.repo_id != other_repository.repo_id {
+                continue;
+            }
+            let transferred_archive = match list_output
+                .archives
+                .iter()
+                .find(|transferred_archive| transferred_archive.name == archive.name)
+            {
+                Some(transferred_archive) => transferred_archive,
+                None => {
+                    println!("Missing transferred archive: {}", archive.name);
+                    continue;
+                }
+            };
+            if catalog
+                .find_archive_by_repo_id_and_archive_id(
+                    &list_output.repository.id,
+                    &transferred_archive.id,
+                )
+                .await?
+                .is_some()
+            {
+                continue;
+            }
+            create_transferred_archive(
+                catalog,
+                &directory.id,
+                &archive,
+                &list_output.repository.id,
+                &transferred_archive.id,
+            )
+            .await?;
+            println!("Transferred archive: {}", archive.name);
+        }
     }
+    Ok(())
 }
 
-fn get_archive_prefix(path: &str) -> String {
-    path.split(|c| c == '/' || c == '_')
-        .map(|component| any_ascii(component))
-        .collect::<Vec<String>>()
-        .join("-")
-        .replace(" ", "")
-        .replace("--", "-")
-        .trim_start_matches("-")
-        .to_lowercase()
+pub async fn reconcile(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: &str,
+    fix: bool,
+) -> Result<(), Error> {
+    let repo = &find_repo_location(catalog, repo).await?;
+    let list_output = archiver.list(repo).await.map_err(Error::Archiver)?;
+    let repo_id = &list_output.repository.id;
+    let archives = catalog.find_archives_by_repo_id(repo_id).await?;
+    for archive in &archives {
+        if list_output
+            .archives
+            .iter()
+            .any(|repo_archive| repo_archive.id == archive.archive_id)
+        {
+            continue;
+        }
+        if fix {
+            catalog.delete_archive(&archive.id).await?;
+            println!("Removed missing archive: {} [{}]", archive.name, archive.id);
+        } else {
+            println!("Missing archive: {} [{}]", archive.name, archive.id);
+        }
+    }
+    let directories = catalog.find_directories().await?;
+    for repo_archive in &list_output.archives {
+        if archives
+            .iter()
+            .any(|archive| archive.archive_id == repo_archive.id)
+        {
+            continue;
+        }
+        let matching_directories = match_archive_directories(&directories, &repo_archive.name);
+        let directory = match matching_directories.as_slice() {
+            [directory] => *directory,
+            [] => {
+                println!(
+                    "Unresolved orphaned archive: {} (no directory)",
+                    repo_archive.name
+                );
+                continue;
+            }
+            _ => {
+                println!(
+                    "Unresolved orphaned archive: {} (ambiguous directory)",
+                    repo_archive.name
+                );
+                continue;
+            }
+        };
+        if !fix {
+            println!(
+                "Orphaned archive: {} ({})",
+                repo_archive.name, directory.path
+            );
+            continue;
+        }
+        import_archive(catalog, repo_id, repo_archive, Some(directory)).await?;
+        println!(
+            "Adopted orphaned archive: {} ({})",
+            repo_archive.name, directory.path
+        );
+    }
+    Ok(())
 }
 
-fn compute_directory_hash(path: &str) -> Hash {
-    let mut hasher = Hasher::new();
-    WalkDir::new(path)
-        .into_iter()
-        .filter_map(|v| v.ok())
-        .filter(|e| e.path().is_file())
-        .for_each(|e| {
-            hasher.update_rayon(e.path().as_os_str().to_str().unwrap().as_bytes());
-            hasher.update_rayon(
-                DateTime::<Local>::from(e.metadata().unwrap().modified().unwrap())
-                    .naive_local()
-                    .to_string()
-                    .as_bytes(),
+pub async fn import(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: &str,
+    mapping: Option<&str>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let directories = catalog.find_directories().await?;
+    let mapping = match mapping {
+        Some(mapping) => read_mapping(mapping, &directories).await?,
+        None => Vec::new(),
+    };
+    let repo = &find_repo_location(catalog, repo).await?;
+    let list_output = archiver.list(repo).await.map_err(Error::Archiver)?;
+    let repo_id = &list_output.repository.id;
+    for repo_archive in &list_output.archives {
+        if catalog
+            .find_archive_by_repo_id_and_archive_id(repo_id, &repo_archive.id)
+            .await?
+            .is_some()
+        {
+            continue;
+        }
+        let mapped_directory = mapping
+            .iter()
+            .filter(|(prefix, _)| repo_archive.name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, directory)| *directory);
+        let directory = match mapped_directory {
+            Some(directory) => directory,
+            None => match match_archive_directories(&directories, &repo_archive.name).as_slice() {
+                [directory] => *directory,
+                [] => {
+                    println!("Skipped archive: {} (no directory)", repo_archive.name);
+                    continue;
+                }
+                _ => {
+                    println!(
+                        "Skipped archive: {} (ambiguous directory)",
+                        repo_archive.name
+                    );
+                    continue;
+                }
+            },
+        };
+        if !dry_run {
+            import_archive(catalog, repo_id, repo_archive, Some(directory)).await?;
+        }
+        println!(
+            "Imported archive: {} ({})",
+            repo_archive.name, directory.path
+        );
+    }
+    Ok(())
+}
+
+pub async fn add_repository(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    location: &str,
+    label: &str,
+) -> Result<(), Error> {
+    if catalog.find_repository_by_label(label).await?.is_some() {
+        println!("Repository {} already exists", label);
+        return Ok(());
+    }
+    let info_output = archiver.info(location).await.map_err(Error::Archiver)?;
+    match catalog
+        .find_repository_by_repo_id(&info_output.repository.id)
+        .await?
+    {
+        Some(repository) => println!(
+            "Repository {} is already registered as {}",
+            location, repository.label
+        ),
+        None => {
+            catalog
+                .create_repository(
+                    label,
+                    location,
+                    &info_output.repository.id,
+                    &info_output.encryption.mode,
+                    &Local::now().naive_local(),
+                )
+                .await?;
+            let repository = catalog
+                .find_repository_by_label(label)
+                .await?
+                .ok_or_else(|| Error::NotFound(format!("Repository {} is gone", label)))?;
+            refresh_repository_drive(catalog, &repository).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn list_repositories(catalog: &mut dyn Catalog) -> Result<(), Error> {
+    for repository in catalog.find_repositories().await? {
+        let archive_count = catalog
+            .count_archives_by_repo_id(&repository.repo_id)
+            .await?;
+        println!(
+            "{} {} {} {} archive(s), last seen {} [{}]",
+            repository.label,
+            repository.location,
+            repository.encryption,
+            archive_count,
+            repository.last_seen_date,
+            repository.repo_id
+        );
+    }
+    Ok(())
+}
+
+pub async fn remove_repository(catalog: &mut dyn Catalog, label: &str) -> Result<(), Error> {
+    match catalog.find_repository_by_label(label).await? {
+        Some(repository) => catalog.delete_repository(&repository.id).await?,
+        None => println!("Repository does not exist"),
+    }
+    Ok(())
+}
+
+pub async fn list_drives(catalog: &mut dyn Catalog) -> Result<(), Error> {
+    for repository in catalog.find_repositories().await? {
+        refresh_repository_drive(catalog, &repository).await?;
+    }
+    for drive in catalog.find_drives().await? {
+        println!(
+            "{} {}/{} free, last mounted {} [{}]",
+            drive.label.as_deref().unwrap_or("-"),
+            HumanBytes(u64::try_from(drive.avail)?),
+            HumanBytes(u64::try_from(drive.size)?),
+            drive.last_mounted_date,
+            drive.uuid
+        );
+        for repository in catalog.find_repositories_by_drive_id(&drive.id).await? {
+            println!("    {} {}", repository.label, repository.location);
+        }
+    }
+    Ok(())
+}
+
+pub async fn stats(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    directory_id: Option<&Uuid>,
+    refresh: bool,
+) -> Result<(), Error> {
+    let repositories = match repo {
+        Some(repo) => vec![find_repository(catalog, repo).await?],
+        None => catalog.find_repositories().await?,
+    };
+    if refresh {
+        for repository in &repositories {
+            refresh_archive_stats(catalog, archiver, repository).await?;
+        }
+    }
+    match directory_id {
+        Some(directory_id) => {
+            let directory = catalog.find_directory_by_id(directory_id).await?;
+            println!("{}", directory.path);
+            let mut previous_sizes: HashMap<String, i64> = HashMap::new();
+            let mut archives = catalog.find_archives_by_directory_id(&directory.id).await?;
+            archives.reverse();
+            for archive in archives {
+                let repository = repositories.iter().find(|r| r.repo_id == archive.repo_id);
+                if repository.is_none() && repo.is_some() {
+                    continue;
+                }
+                let label = repository.map_or(archive.repo_id.as_str(), |r| r.label.as_str());
+                match catalog
+                    .find_archive_stats_by_archive_id(&archive.id)
+                    .await?
+                {
+                    Some(archive_stats) => {
+                        let growth = previous_sizes
+                            .insert(archive.repo_id.clone(), archive_stats.original_size)
+                            .map(|previous_size| {
+                                format!(
+                                    ", growth {}",
+                                    format_size_delta(archive_stats.original_size - previous_size)
+                                )
+                            })
+                            .unwrap_or_default();
+                        println!(
+                            "{} {} {} {}{}",
+                            archive.created_date,
+                            archive.name,
+                            label,
+                            format_archive_stats(&archive_stats),
+                            growth
+                        );
+                    }
+                    None => println!(
+                        "{} {} {} no statistics",
+                        archive.created_date, archive.name, label
+                    ),
+                }
+            }
+        }
+        None => {
+            for repository in &repositories {
+                if repo.is_some() {
+                    println!("{}", repository.label);
+                }
+                let mut archives = catalog
+                    .find_archives_by_repo_id(&repository.repo_id)
+                    .await?;
+                archives.reverse();
+                let mut original_size = 0;
+                let mut deduplicated_size = 0;
+                for archive in &archives {
+                    let archive_stats = match catalog
+                        .find_archive_stats_by_archive_id(&archive.id)
+                        .await?
+                    {
+                        Some(archive_stats) => archive_stats,
+                        None => continue,
+                    };
+                    original_size += archive_stats.original_size;
+                    deduplicated_size += archive_stats.deduplicated_size;
+                    if repo.is_some() {
+                        println!(
+                            "    {} {} {}, repository size {}",
+                            archive.created_date,
+                            archive.name,
+                            format_archive_stats(&archive_stats),
+                            HumanBytes(deduplicated_size as u64)
+                        );
+                    }
+                }
+                println!(
+                    "{} {} archive(s), {} original, {} deduplicated, ratio {}",
+                    repository.label,
+                    archives.len(),
+                    HumanBytes(original_size as u64),
+                    HumanBytes(deduplicated_size as u64),
+                    format_ratio(original_size, deduplicated_size)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn locate(catalog: &mut dyn Catalog, directory_id: &Uuid) -> Result<(), Error> {
+    let directory = catalog.find_directory_by_id(directory_id).await?;
+    let archives = catalog.find_archives_by_directory_id(&directory.id).await?;
+    if archives.is_empty() {
+        println!("Not backed up: {}", directory.path);
+        return Ok(());
+    }
+    let mut repo_ids: Vec<&str> = Vec::new();
+    for archive in &archives {
+        if !repo_ids.contains(&archive.repo_id.as_str()) {
+            repo_ids.push(&archive.repo_id);
+        }
+    }
+    for repo_id in repo_ids {
+        let archive = archives.iter().find(|a| a.repo_id == repo_id).unwrap();
+        let repository = match catalog.find_repository_by_repo_id(repo_id).await? {
+            Some(repository) => repository,
+            None => {
+                println!("{} is in unregistered repository {}", archive.name, repo_id);
+                continue;
+            }
+        };
+        if Path::new(&repository.location).is_dir().await {
+            println!(
+                "{} is available in {} at {}",
+                archive.name, repository.label, repository.location
             );
-        });
-    hasher.finalize()
+            continue;
+        }
+        match repository.drive_id {
+            Some(drive_id) => {
+                let drive = catalog.find_drive_by_id(&drive_id).await?;
+                println!(
+                    "{} is in {}, plug in drive {} [{}]",
+                    archive.name,
+                    repository.label,
+                    drive.label.as_deref().unwrap_or("-"),
+                    drive.uuid
+                );
+            }
+            None => println!(
+                "{} is in {} at {}, which is unreachable",
+                archive.name, repository.label, repository.location
+            ),
+        }
+    }
+    Ok(())
+}
+
+pub async fn plan(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    config: &Config,
+    execute: bool,
+    dry_run: bool,
+) -> Result<Failures, Error> {
+    let mut failures: Failures = Vec::new();
+    let mut bins: Vec<Bin> = Vec::new();
+    for repository in catalog.find_repositories().await? {
+        refresh_repository_drive(catalog, &repository).await?;
+        let repository = catalog
+            .find_repository_by_repo_id(&repository.repo_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Repository {} is gone", repository.label)))?;
+        let mounted = Path::new(&repository.location).is_dir().await;
+        match repository.drive_id {
+            Some(drive_id) => {
+                if bins
+                    .iter()
+                    .any(|bin| bin.repository.drive_id == Some(drive_id))
+                {
+                    continue;
+                }
+                let drive = catalog.find_drive_by_id(&drive_id).await?;
+                bins.push(Bin {
+                    label: drive.label.unwrap_or(drive.uuid),
+                    repository,
+                    size: drive.size,
+                    avail: drive.avail,
+                    mounted,
+                    directories: Vec::new(),
+                });
+            }
+            None => {
+                if !mounted {
+                    info!("Repository {} is unreachable", repository.label);
+                    continue;
+                }
+                let filesystem_space = space::filesystem(&repository.location).await?;
+                bins.push(Bin {
+                    label: repository.location.clone(),
+                    repository,
+                    size: i64::try_from(filesystem_space.size)?,
+                    avail: i64::try_from(filesystem_space.avail)?,
+                    mounted,
+                    directories: Vec::new(),
+                });
+            }
+        }
+    }
+
+    let mut directories: Vec<(Directory, i64)> = Vec::new();
+    for directory in catalog.find_directories_without_archives().await? {
+        let filter = find_exclusion_filter(catalog, config, &directory, &directory.path).await?;
+        let mut size = space::directory(&filter).await?.apparent;
+        if let Some(compression) = config.compression(&directory.path) {
+            size = (size as f64 * estimate::compression_ratio(&filter, compression).await?) as u64;
+        }
+        directories.push((directory, i64::try_from(size)?));
+    }
+    directories.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    let mut unassigned: Vec<(Directory, i64)> = Vec::new();
+    for (directory, size) in directories {
+        match bins
+            .iter_mut()
+            .find(|bin| bin.fits(size, config.margin / 100.0))
+        {
+            Some(bin) => bin.directories.push((directory, size)),
+            None => unassigned.push((directory, size)),
+        }
+    }
+
+    for bin in &bins {
+        if bin.directories.is_empty() {
+            continue;
+        }
+        println!(
+            "{} on {}{}:",
+            bin.repository.label,
+            bin.label,
+            if bin.mounted { "" } else { " (offline)" }
+        );
+        for (directory, size) in &bin.directories {
+            println!(
+                "    {} {} [{}]",
+                directory.path,
+                HumanBytes(u64::try_from(*size)?),
+                directory.id
+            );
+        }
+    }
+    for (directory, size) in &unassigned {
+        println!(
+            "Does not fit: {} {} [{}]",
+            directory.path,
+            HumanBytes(u64::try_from(*size)?),
+            directory.id
+        );
+    }
+
+    if execute {
+        for bin in &bins {
+            if !bin.mounted {
+                if !bin.directories.is_empty() {
+                    println!(
+                        "Skipping {}, plug in drive {}",
+                        bin.repository.label, bin.label
+                    );
+                }
+                continue;
+            }
+            for (directory, _) in &bin.directories {
+                match create(
+                    catalog,
+                    archiver,
+                    &bin.repository.location,
+                    &directory.id,
+                    config,
+                    dry_run,
+                    false,
+                )
+                .await
+                {
+                    Ok(mut directory_failures) => failures.append(&mut directory_failures),
+                    Err(error) => failures.push((directory.path.clone(), error)),
+                }
+            }
+        }
+    }
+    Ok(failures)
+}
+
+async fn scan_root_directory(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    root_directory: &RootDirectory,
+    incremental: bool,
+) -> Result<(), Error> {
+    let patterns = find_effective_exclusion_patterns(
+        catalog,
+        config,
+        &root_directory.path,
+        &root_directory.id,
+        None,
+    )
+    .await?;
+    let mut filter = get_exclusion_filter(&root_directory.path, &patterns)?;
+    let depth = usize::try_from(root_directory.depth)?;
+    let paths: Vec<String> = WalkDir::new(&root_directory.path)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() <= depth
+                && e.path().is_dir()
+                && !e.file_name().to_string_lossy().starts_with('.')
+                && !filter.is_excluded(e)
+        })
+        .filter_map(|v| v.ok())
+        .filter(|e| e.depth() == depth)
+        .map(|e| to_str(e.path().as_os_str()).map(String::from))
+        .collect::<Result<Vec<String>, Error>>()?;
+    hash_directories(catalog, config, root_directory, &paths, incremental).await?;
+    Ok(())
+}
+
+async fn hash_directories(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    root_directory: &RootDirectory,
+    paths: &[String],
+    incremental: bool,
+) -> Result<(), Error> {
+    let fingerprint = &root_directory.fingerprint;
+    let mut caches: Vec<HashMap<String, fingerprint::Entry>> = Vec::new();
+    let mut filters: Vec<Filter> = Vec::new();
+    for path in paths {
+        let directory = catalog.find_directory_by_path(path).await?;
+        let patterns = find_effective_exclusion_patterns(
+            catalog,
+            config,
+            path,
+            &root_directory.id,
+            directory.as_ref().map(|directory| &directory.id),
+        )
+        .await?;
+        filters.push(get_exclusion_filter(path, &patterns)?);
+        let cache = match directory {
+            Some(directory) if incremental => catalog
+                .find_manifest_entries_by_directory_id_and_archive_id(&directory.id, None)
+                .await?
+                .into_iter()
+                .map(|e| get_fingerprint_entry(e).map(|entry| (entry.path.clone(), entry)))
+                .collect::<Result<_, Error>>()?,
+            _ => HashMap::new(),
+        };
+        caches.push(cache);
+    }
+    let walks: Vec<Vec<fingerprint::Entry>> = filters
+        .par_iter_mut()
+        .zip(caches.par_iter())
+        .map(|(filter, cache)| fingerprint::walk(filter, fingerprint, cache))
+        .collect::<Result<Vec<Vec<fingerprint::Entry>>, std::io::Error>>()?;
+    for (path, entries) in paths.iter().zip(walks) {
+        let blake3_hash = fingerprint::compute_entries_hash(&entries)?;
+        let directory = catalog.find_directory_by_path(path).await?;
+        let (directory_id, changed) = match directory {
+            Some(directory) => {
+                let changed = directory.blake3_hash == UNKNOWN_HASH
+                    || parse_hash(&directory.blake3_hash)? != blake3_hash
+                    || fingerprint != &directory.fingerprint;
+                if changed {
+                    catalog
+                        .update_directory(&directory.id, &blake3_hash.to_hex(), fingerprint)
+                        .await?;
+                } else if directory.dirty {
+                    catalog.update_directory_dirty(&directory.id, false).await?;
+                }
+                (directory.id, changed)
+            }
+            None => {
+                let directory_id = catalog
+                    .create_directory(path, &blake3_hash.to_hex(), fingerprint, &root_directory.id)
+                    .await?;
+                (directory_id, true)
+            }
+        };
+        if changed
+            || !catalog
+                .exists_manifest_entries_by_directory_id(&directory_id)
+                .await?
+        {
+            catalog
+                .delete_manifest_entries_by_directory_id(&directory_id)
+                .await?;
+            catalog
+                .create_manifest_entries(&directory_id, &get_manifest_entries(&entries)?)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn mark_dirty_directories(
+    catalog: &mut dyn Catalog,
+    root_directories: &[RootDirectory],
+    changed_paths: impl Iterator<Item = PathBuf>,
+) -> Result<HashSet<Uuid>, Error> {
+    let mut directory_paths: HashSet<String> = HashSet::new();
+    let mut stale_root_directory_ids: HashSet<Uuid> = HashSet::new();
+    for changed_path in changed_paths {
+        let root_directory = root_directories
+            .iter()
+            .find(|root_directory| changed_path.starts_with(&root_directory.path));
+        let root_directory = match root_directory {
+            Some(root_directory) => root_directory,
+            None => continue,
+        };
+        let depth = usize::try_from(root_directory.depth)?;
+        let components: Vec<&OsStr> = changed_path
+            .strip_prefix(&root_directory.path)
+            .unwrap()
+            .iter()
+            .take(depth)
+            .collect();
+        if components
+            .iter()
+            .any(|component| component.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if components.len() < depth {
+            stale_root_directory_ids.insert(root_directory.id);
+            continue;
+        }
+        let mut directory_path = PathBuf::from(&root_directory.path);
+        directory_path.extend(components);
+        directory_paths.insert(to_str(directory_path.as_os_str())?.to_string());
+    }
+    for directory_path in directory_paths {
+        match catalog.find_directory_by_path(&directory_path).await? {
+            Some(directory) => {
+                if !directory.dirty {
+                    debug!("Marking {} dirty", &directory.path);
+                    catalog.update_directory_dirty(&directory.id, true).await?;
+                }
+            }
+            None => {
+                let root_directory = root_directories
+                    .iter()
+                    .find(|root_directory| directory_path.starts_with(&root_directory.path))
+                    .unwrap();
+                stale_root_directory_ids.insert(root_directory.id);
+            }
+        }
+    }
+    Ok(stale_root_directory_ids)
+}
+
+async fn remove_missing_directories(catalog: &mut dyn Catalog) -> Result<(), Error> {
+    let directories = catalog.find_directories().await?;
+    for directory in directories {
+        if !Path::new(&directory.path).is_dir().await {
+            catalog.delete_directory(&directory.id).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn report(catalog: &mut dyn Catalog) -> Result<(), Error> {
+    let directories = catalog.find_directories().await?;
+    for directory in directories {
+        if directory.dirty {
+            println!(
+                "Pending rescan: {} [{}:{}]",
+                &directory.path, &directory.root_directory_id, &directory.id
+            );
+        }
+        let archive = catalog
+            .find_latest_archive_by_directory_id(&directory.id)
+            .await?;
+        match archive {
+            Some(archive) => {
+                if archive.blake3_hash == UNKNOWN_HASH {
+                    println!(
+                        "Unverified: {} [{}:{}]",
+                        &directory.path, &directory.root_directory_id, &directory.id
+                    );
+                } else if archive.blake3_hash != directory.blake3_hash
+                    || archive.fingerprint != directory.fingerprint
+                {
+                    println!(
+                        "Out of date: {} [{}:{}]",
+                        &directory.path, &directory.root_directory_id, &directory.id
+                    );
+                }
+                let copies = find_effective_copies(catalog, &directory).await?;
+                let repo_ids = catalog.find_repo_ids_by_directory_id(&directory.id).await?;
+                if repo_ids.len() < usize::try_from(copies)? {
+                    println!(
+                        "Under-replicated: {} {}/{} [{}:{}]",
+                        &directory.path,
+                        repo_ids.len(),
+                        copies,
+                        &directory.root_directory_id,
+                        &directory.id
+                    );
+                }
+            }
+            None => println!(
+                "Not backed up: {} [{}:{}]",
+                &directory.path, &directory.root_directory_id, &directory.id
+            ),
+        }
+    }
+    Ok(())
+}
+
+async fn import_archive(
+    catalog: &mut dyn Catalog,
+    repo_id: &str,
+    repo_archive: &archiver::Archive,
+    directory: Option<&Directory>,
+) -> Result<(), Error> {
+    let archive = Archive {
+        id: Uuid::new_v4(),
+        name: repo_archive.name.clone(),
+        repo_id: repo_id.to_string(),
+        archive_id: repo_archive.id.clone(),
+        created_date: repo_archive.start,
+        blake3_hash: String::from(UNKNOWN_HASH),
+        directory_id: directory.map(|directory| directory.id),
+        fingerprint: directory.map_or_else(
+            || String::from(fingerprint::METADATA),
+            |directory| directory.fingerprint.clone(),
+        ),
+    };
+    catalog.create_archive(&archive).await
+}
+
+async fn read_mapping<'a>(
+    path: &str,
+    directories: &'a [Directory],
+) -> Result<Vec<(String, &'a Directory)>, Error> {
+    let mut mapping: Vec<(String, &Directory)> = Vec::new();
+    for line in fs::read_to_string(path).await?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (prefix, directory_path) = line
+            .split_once(char::is_whitespace)
+            .map(|(prefix, directory_path)| (prefix, directory_path.trim()))
+            .ok_or_else(|| Error::Config(format!("Invalid mapping line in {}: {}", path, line)))?;
+        let directory = directories
+            .iter()
+            .find(|directory| directory.path == directory_path)
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "Directory {} in {} is not watched",
+                    directory_path, path
+                ))
+            })?;
+        mapping.push((prefix.to_string(), directory));
+    }
+    Ok(mapping)
+}
+
+async fn find_repo_location(catalog: &mut dyn Catalog, repo: &str) -> Result<String, Error> {
+    match catalog.find_repository_by_label(repo).await? {
+        Some(repository) => Ok(repository.location),
+        None => Ok(repo.to_string()),
+    }
+}
+
+async fn find_repository(catalog: &mut dyn Catalog, repo: &str) -> Result<Repository, Error> {
+    catalog
+        .find_repositories()
+        .await?
+        .into_iter()
+        .find(|repository| repository.label == repo || repository.location == repo)
+        .ok_or_else(|| Error::NotFound(format!("Repository {} is not registered", repo)))
+}
+
+async fn find_latest_archive(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    directory: &Directory,
+) -> Result<Archive, Error> {
+    match repo {
+        Some(repo) => {
+            let repo = find_repo_location(catalog, repo).await?;
+            let repo_id = archiver
+                .info(&repo)
+                .await
+                .map_err(Error::Archiver)?
+                .repository
+                .id;
+            catalog
+                .find_latest_archive_by_directory_id_and_repo_id(&directory.id, &repo_id)
+                .await?
+        }
+        None => {
+            catalog
+                .find_latest_archive_by_directory_id(&directory.id)
+                .await?
+        }
+    }
+    .ok_or_else(|| Error::NotFound(format!("No archive for {}", directory.path)))
+}
+
+async fn find_archive_repo_location(
+    catalog: &mut dyn Catalog,
+    repo: Option<&str>,
+    archive: &Archive,
+) -> Result<String, Error> {
+    match repo {
+        Some(repo) => find_repo_location(catalog, repo).await,
+        None => Ok(catalog
+            .find_repository_by_repo_id(&archive.repo_id)
+            .await?
+            .ok_or_else(|| {
+                Error::NotFound(format!("Repository {} is not registered", &archive.repo_id))
+            })?
+            .location),
+    }
+}
+
+async fn check_archive_repository(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    archive: &Archive,
+    repo: &str,
+) -> Result<(), Error> {
+    let repo_id = archiver
+        .info(repo)
+        .await
+        .map_err(Error::Archiver)?
+        .repository
+        .id;
+    if archive.repo_id != repo_id {
+        return Err(Error::Conflict(format!(
+            "Archive {} is not in repository {}",
+            archive.name, repo
+        )));
+    }
+    if let Some(repository) = catalog.find_repository_by_repo_id(&repo_id).await? {
+        catalog
+            .update_repository_last_seen_date(&repository.id, &Local::now().naive_local())
+            .await?;
+        refresh_repository_drive(catalog, &repository).await?;
+    }
+    Ok(())
+}
+
+async fn refresh_archive_stats(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repository: &Repository,
+) -> Result<(), Error> {
+    if !Path::new(&repository.location).is_dir().await {
+        info!("Repository {} is not mounted locally", repository.label);
+        return Ok(());
+    }
+    for archive in catalog
+        .find_archives_by_repo_id(&repository.repo_id)
+        .await?
+    {
+        if catalog
+            .find_archive_stats_by_archive_id(&archive.id)
+            .await?
+            .is_some()
+        {
+            continue;
+        }
+        let info_output = match archiver
+            .info_archive(&repository.location, &archive.name)
+            .await
+        {
+            Ok(info_output) => info_output,
+            Err(error) if error.kind() == ErrorKind::Unsupported => {
+                println!("Skipping repository {}: {}", repository.label, error);
+                return Ok(());
+            }
+            Err(error) => return Err(Error::Archiver(error)),
+        };
+        if let Some(stats) = &info_output.stats {
+            catalog
+                .create_archive_stats(&get_archive_stats(
+                    &archive.id,
+                    stats,
+                    info_output.duration,
+                )?)
+                .await?;
+            println!("Refreshed statistics of archive {}", archive.name);
+        }
+    }
+    Ok(())
+}
+
+async fn refresh_repository_drive(
+    catalog: &mut dyn Catalog,
+    repository: &Repository,
+) -> Result<(), Error> {
+    if !Path::new(&repository.location).is_dir().await {
+        info!("Repository {} is not mounted locally", repository.label);
+        return Ok(());
+    }
+    let findmnt_output = findmnt::main(&repository.location).await?;
+    let filesystem = match findmnt_output.filesystems.first() {
+        Some(filesystem) => filesystem,
+        None => return Ok(()),
+    };
+    let uuid = match &filesystem.uuid {
+        Some(uuid) => uuid,
+        None => {
+            info!("Filesystem {} has no UUID", filesystem.target);
+            return Ok(());
+        }
+    };
+    let filesystem_space = space::filesystem(&repository.location).await?;
+    let size = i64::try_from(filesystem_space.size)?;
+    let avail = i64::try_from(filesystem_space.avail)?;
+    let drive_id = match catalog.find_drive_by_uuid(uuid).await? {
+        Some(drive) => {
+            catalog
+                .update_drive(
+                    &drive.id,
+                    filesystem.label.as_deref(),
+                    size,
+                    avail,
+                    &Local::now().naive_local(),
+                )
+                .await?;
+            drive.id
+        }
+        None => {
+            catalog
+                .create_drive(
+                    uuid,
+                    filesystem.label.as_deref(),
+                    size,
+                    avail,
+                    &Local::now().naive_local(),
+                )
+                .await?
+        }
+    };
+    if repository.drive_id != Some(drive_id) {
+        catalog
+            .update_repository_drive_id(&repository.id, &drive_id)
+            .await?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn create_directory(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: &str,
+    repo_id: &str,
+    directory: &Directory,
+    config: &Config,
+    dry_run: bool,
+) -> Result<(), Error> {
+    if let Some(archive) = catalog
+        .find_latest_archive_by_directory_id_and_repo_id(&directory.id, repo_id)
+        .await?
+    {
+        return Err(Error::Conflict(format!(
+            "Archive {} already exists",
+            &archive.name
+        )));
+    };
+    archive_directory(
+        catalog,
+        archiver,
+        repo,
+        directory,
+        &directory.path,
+        None,
+        config,
+        dry_run,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn update_directory(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    directory: &Directory,
+    config: &Config,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let archive = find_latest_archive(catalog, archiver, repo, directory).await?;
+    let repo = &find_archive_repo_location(catalog, repo, &archive).await?;
+    check_archive_repository(catalog, archiver, &archive, repo).await?;
+    archive_directory(
+        catalog,
+        archiver,
+        repo,
+        directory,
+        &directory.path,
+        None,
+        config,
+        dry_run,
+    )
+    .await?;
+    prune_directory(catalog, archiver, repo, directory, config, dry_run).await
+}
+
+async fn prune_archived_directory(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: Option<&str>,
+    directory: &Directory,
+    config: &Config,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let archive = find_latest_archive(catalog, archiver, repo, directory).await?;
+    let repo = &find_archive_repo_location(catalog, repo, &archive).await?;
+    check_archive_repository(catalog, archiver, &archive, repo).await?;
+    prune_directory(catalog, archiver, repo, directory, config, dry_run).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn replicate_directory(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: &str,
+    repo_id: &str,
+    directory: &Directory,
+    config: &Config,
+    transfer: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    if catalog
+        .find_latest_archive_by_directory_id_and_repo_id(&directory.id, repo_id)
+        .await?
+        .is_some()
+    {
+        println!("Already replicated: {}", directory.path);
+        return Ok(());
+    }
+    if !transfer {
+        return archive_directory(
+            catalog,
+            archiver,
+            repo,
+            directory,
+            &directory.path,
+            None,
+            config,
+            dry_run,
+        )
+        .await;
+    }
+    let archive = catalog
+        .find_latest_archive_by_directory_id(&directory.id)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("No archive to transfer for {}", directory.path)))?;
+    let source_repo = &find_archive_repo_location(catalog, None, &archive).await?;
+    check_archive_repository(catalog, archiver, &archive, source_repo).await?;
+    match archiver
+        .transfer(repo, source_repo, Some(&archive.name), dry_run)
+        .await
+    {
+        Ok(()) if dry_run => return Ok(()),
+        Ok(()) => {
+            let transferred_archive = archiver
+                .info_archive(repo, &archive.name)
+                .await
+                .map_err(Error::Archiver)?;
+            return create_transferred_archive(
+                catalog,
+                &directory.id,
+                &archive,
+                repo_id,
+                &transferred_archive.id,
+            )
+            .await;
+        }
+        Err(error) if error.kind() == ErrorKind::Unsupported => {
+            debug!("{}, extracting {} instead", error, archive.name)
+        }
+        Err(error) => return Err(Error::Archiver(error)),
+    }
+    let tmp_path = env::temp_dir().join(format!("permafrust-{}", Uuid::new_v4()));
+    let tmp_path = to_str(tmp_path.as_os_str())?;
+    let source = Directory {
+        id: directory.id,
+        path: directory.path.clone(),
+        blake3_hash: archive.blake3_hash.clone(),
+        root_directory_id: directory.root_directory_id,
+        fingerprint: archive.fingerprint.clone(),
+        dirty: false,
+    };
+    let result = match archiver
+        .extract(source_repo, &archive.name, tmp_path, dry_run)
+        .await
+    {
+        Ok(()) if dry_run => Ok(()),
+        Ok(()) => {
+            archive_directory(
+                catalog,
+                archiver,
+                repo,
+                &source,
+                tmp_path,
+                Some(&archive.id),
+                config,
+                dry_run,
+            )
+            .await
+        }
+        Err(error) => Err(Error::Archiver(error)),
+    };
+    if Path::new(tmp_path).is_dir().await {
+        fs::remove_dir_all(tmp_path).await?;
+    }
+    result
+}
+
+async fn create_transferred_archive(
+    catalog: &mut dyn Catalog,
+    directory_id: &Uuid,
+    archive: &Archive,
+    repo_id: &str,
+    archive_id: &str,
+) -> Result<(), Error> {
+    let new_archive = Archive {
+        id: Uuid::new_v4(),
+        repo_id: repo_id.to_string(),
+        archive_id: archive_id.to_string(),
+        ..archive.clone()
+    };
+    catalog.create_archive(&new_archive).await?;
+    catalog
+        .copy_manifest_entries(directory_id, Some(&archive.id), &new_archive.id)
+        .await?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn estimate_archive_size(
+    archiver: &dyn Archiver,
+    repo: &str,
+    prefix: &str,
+    filter: &Filter,
+    patterns: &[String],
+    compression: &str,
+    config: &Config,
+    size: u64,
+) -> Result<u64, Error> {
+    let path = to_str(filter.path().as_os_str())?;
+    if config.estimate_dry_run && !archiver.reports_dry_run_stats() {
+        debug!(
+            "The archiver reports no dry run statistics, estimating {} from samples",
+            path
+        );
+    } else if config.estimate_dry_run {
+        match archiver
+            .create(
+                repo,
+                prefix,
+                path,
+                patterns,
+                compression,
+                true,
+                &ProgressBar::hidden(),
+            )
+            .await
+        {
+            Ok(create_output) => match create_output.archive.stats {
+                Some(stats) => return Ok(stats.deduplicated_size),
+                None => warn!(
+                    "Dry run of {} reported no statistics, estimating from samples",
+                    path
+                ),
+            },
+            Err(error) => warn!(
+                "Dry run of {} failed, estimating from samples: {}",
+                path, error
+            ),
+        }
+    }
+    let compression_ratio = estimate::compression_ratio(filter, compression).await?;
+    Ok((size as f64 * compression_ratio) as u64)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn archive_directory(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: &str,
+    directory: &Directory,
+    path: &str,
+    source_archive_id: Option<&Uuid>,
+    config: &Config,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let compression = config
+        .compression(&directory.path)
+        .ok_or_else(|| Error::Config(format!("No compression specified for {}", directory.path)))?;
+    let mut hook_env = vec![
+        ("PERMAFRUST_DIRECTORY", directory.path.clone()),
+        ("PERMAFRUST_REPO", repo.to_string()),
+    ];
+    if let (Some(hook), false) = (&config.hooks.pre_create, dry_run) {
+        run_hook(hook, &hook_env).await?;
+    }
+    // The last scan may be stale, record what is archived now
+    let directory = &match (source_archive_id, dry_run) {
+        (None, false) => rehash_directory(catalog, config, directory).await?,
+        _ => directory.clone(),
+    };
+    let prefix = get_archive_prefix(&directory.path);
+    let mut filter = find_exclusion_filter(catalog, config, directory, path).await?;
+    let patterns = filter.patterns();
+    let filesystem_space = space::filesystem(repo).await?;
+    let directory_size = space::directory(&filter).await?;
+    let estimated_size = estimate_archive_size(
+        archiver,
+        repo,
+        &prefix,
+        &filter,
+        &patterns,
+        compression,
+        config,
+        directory_size.apparent,
+    )
+    .await?;
+    let remaining_space_after =
+        (filesystem_space.avail as f64 - estimated_size as f64) / filesystem_space.size as f64;
+    info!(
+        "Estimated size: {}, remaining space after: {}",
+        estimated_size, remaining_space_after
+    );
+    if remaining_space_after < config.margin / 100.0 {
+        return Err(Error::InsufficientSpace(format!(
+            "Not enough space in {} for {} ({} estimated, {} available)",
+            repo,
+            path,
+            HumanBytes(estimated_size),
+            HumanBytes(filesystem_space.avail)
+        )));
+    }
+    let progress_bar = progress::bytes(&directory.path, directory_size.apparent);
+    let create_output = archiver
+        .create(
+            repo,
+            &prefix,
+            path,
+            &patterns,
+            compression,
+            dry_run,
+            &progress_bar,
+        )
+        .await
+        .map_err(Error::Archiver)?;
+    if dry_run {
+        return Ok(());
+    }
+    hook_env.push(("PERMAFRUST_ARCHIVE", create_output.archive.name.clone()));
+    let archive = Archive {
+        id: Uuid::new_v4(),
+        name: create_output.archive.name,
+        repo_id: create_output.repository.id,
+        archive_id: create_output.archive.id,
+        created_date: Local::now().naive_local(),
+        blake3_hash: directory.blake3_hash.clone(),
+        directory_id: Some(directory.id),
+        fingerprint: directory.fingerprint.clone(),
+    };
+    catalog.create_archive(&archive).await?;
+    if let Some(stats) = &create_output.archive.stats {
+        catalog
+            .create_archive_stats(&get_archive_stats(
+                &archive.id,
+                stats,
+                create_output.archive.duration,
+            )?)
+            .await?;
+    }
+    catalog
+        .copy_manifest_entries(&directory.id, source_archive_id, &archive.id)
+        .await?;
+    if let Some(hook) = &config.hooks.post_create {
+        run_hook(hook, &hook_env).await?;
+    }
+    Ok(())
+}
+
+async fn rehash_directory(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    directory: &Directory,
+) -> Result<Directory, Error> {
+    let root_directory = catalog
+        .find_root_directories()
+        .await?
+        .into_iter()
+        .find(|root_directory| root_directory.id == directory.root_directory_id)
+        .ok_or_else(|| Error::NotFound(format!("No root directory for {}", directory.path)))?;
+    hash_directories(
+        catalog,
+        config,
+        &root_directory,
+        std::slice::from_ref(&directory.path),
+        true,
+    )
+    .await?;
+    catalog.find_directory_by_id(&directory.id).await
+}
+
+async fn run_hook(hook: &str, env: &[(&str, String)]) -> Result<(), Error> {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(hook)
+        .envs(env.iter().map(|(key, value)| (key, value)));
+    debug!("{:?}", command);
+    let status = command.status().await?;
+    if !status.success() {
+        return Err(Error::Hook(format!(
+            "Hook `{}` failed with {}",
+            hook, status
+        )));
+    }
+    Ok(())
+}
+
+async fn prune_directory(
+    catalog: &mut dyn Catalog,
+    archiver: &dyn Archiver,
+    repo: &str,
+    directory: &Directory,
+    config: &Config,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let retention_policy = match find_effective_retention_policy(catalog, config, directory).await?
+    {
+        Some(retention_policy) => retention_policy,
+        None => {
+            info!(
+                "No retention policy for {}, keeping all archives",
+                directory.path
+            );
+            return Ok(());
+        }
+    };
+    let prefix = get_archive_prefix(&directory.path);
+    archiver
+        .prune(repo, &prefix, &retention_policy, dry_run)
+        .await
+        .map_err(Error::Archiver)?;
+    if dry_run {
+        return Ok(());
+    }
+    let list_output = archiver.list(repo).await.map_err(Error::Archiver)?;
+    for archive in catalog.find_archives_by_directory_id(&directory.id).await? {
+        if archive.repo_id == list_output.repository.id
+            && !list_output
+                .archives
+                .iter()
+                .any(|a| a.id == archive.archive_id)
+        {
+            info!("Archive {} was pruned", archive.name);
+            catalog.delete_archive(&archive.id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the narrowest scope first: the directory and its root in the
+/// catalog, then its root in the configuration, then the catalog and
+/// configuration defaults.
+async fn find_effective_retention_policy(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    directory: &Directory,
+) -> Result<Option<RetentionPolicy>, Error> {
+    if let Some(retention_policy) = catalog
+        .find_retention_policy_by_scope(None, Some(&directory.id))
+        .await?
+    {
+        return Ok(Some(retention_policy));
+    }
+    if let Some(retention_policy) = catalog
+        .find_retention_policy_by_scope(Some(&directory.root_directory_id), None)
+        .await?
+    {
+        return Ok(Some(retention_policy));
+    }
+    if let Some(retention_policy) = config.root_retention_policy(&directory.path) {
+        return Ok(Some(retention_policy));
+    }
+    if let Some(retention_policy) = catalog.find_retention_policy_by_scope(None, None).await? {
+        return Ok(Some(retention_policy));
+    }
+    Ok(config.retention_policy())
+}
+
+/// Concatenates configured and catalog patterns from the broadest scope to
+/// the narrowest, so that later ones can re-include what earlier ones exclude.
+async fn find_effective_exclusion_patterns(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    path: &str,
+    root_directory_id: &Uuid,
+    directory_id: Option<&Uuid>,
+) -> Result<Vec<String>, Error> {
+    let mut patterns = config.exclude(path);
+    let mut scopes = vec![(None, None), (Some(root_directory_id), None)];
+    if let Some(directory_id) = directory_id {
+        scopes.push((None, Some(directory_id)));
+    }
+    for (root_directory_id, directory_id) in scopes {
+        if let Some(exclusion_policy) = catalog
+            .find_exclusion_policy_by_scope(root_directory_id, directory_id)
+            .await?
+        {
+            patterns.extend(exclusion_policy.patterns.lines().map(String::from));
+        }
+    }
+    Ok(patterns)
+}
+
+async fn find_exclusion_filter(
+    catalog: &mut dyn Catalog,
+    config: &Config,
+    directory: &Directory,
+    path: &str,
+) -> Result<Filter, Error> {
+    let patterns = find_effective_exclusion_patterns(
+        catalog,
+        config,
+        &directory.path,
+        &directory.root_directory_id,
+        Some(&directory.id),
+    )
+    .await?;
+    get_exclusion_filter(path, &patterns)
+}
+
+async fn find_effective_copies(
+    catalog: &mut dyn Catalog,
+    directory: &Directory,
+) -> Result<i16, Error> {
+    if let Some(replication_policy) = catalog
+        .find_replication_policy_by_scope(None, Some(&directory.id))
+        .await?
+    {
+        return Ok(replication_policy.copies);
+    }
+    if let Some(replication_policy) = catalog
+        .find_replication_policy_by_scope(Some(&directory.root_directory_id), None)
+        .await?
+    {
+        return Ok(replication_policy.copies);
+    }
+    Ok(catalog
+        .find_replication_policy_by_scope(None, None)
+        .await?
+        .map_or(1, |replication_policy| replication_policy.copies))
+}
+
+fn get_exclusion_filter(path: &str, patterns: &[String]) -> Result<Filter, Error> {
+    Filter::new(path, patterns)
+        .map_err(|error| Error::Config(format!("Invalid exclude for {}: {}", path, error)))
+}
+
+fn get_manifest_entries(entries: &[fingerprint::Entry]) -> Result<Vec<ManifestEntry>, Error> {
+    entries
+        .iter()
+        .filter(|entry| matches!(entry.kind, fingerprint::Kind::File))
+        .map(|entry| {
+            let modified_date = entry.modified.naive_utc();
+            Ok(ManifestEntry {
+                path: entry.path.clone(),
+                size: i64::try_from(entry.size)?,
+                modified_date: modified_date
+                    .with_nanosecond(modified_date.nanosecond() / 1000 * 1000)
+                    .ok_or_else(|| {
+                        Error::Io(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("Modification time of {} is not valid", entry.path),
+                        ))
+                    })?,
+                inode: i64::try_from(entry.inode)?,
+                blake3_hash: entry.blake3_hash.map(|hash| hash.to_hex().to_string()),
+            })
+        })
+        .collect()
+}
+
+fn get_fingerprint_entry(manifest_entry: ManifestEntry) -> Result<fingerprint::Entry, Error> {
+    Ok(fingerprint::Entry {
+        path: manifest_entry.path,
+        kind: fingerprint::Kind::File,
+        mode: 0,
+        size: u64::try_from(manifest_entry.size)?,
+        modified: DateTime::<Utc>::from_utc(manifest_entry.modified_date, Utc),
+        inode: u64::try_from(manifest_entry.inode)?,
+        blake3_hash: manifest_entry
+            .blake3_hash
+            .map(|blake3_hash| parse_hash(&blake3_hash))
+            .transpose()?,
+    })
+}
+
+fn parse_hash(blake3_hash: &str) -> Result<Hash, Error> {
+    Hash::from_hex(blake3_hash).map_err(|error| {
+        Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid BLAKE3 hash {}: {}", blake3_hash, error),
+        ))
+    })
+}
+
+fn get_archive_stats(
+    archive_id: &Uuid,
+    stats: &Stats,
+    duration: Option<f64>,
+) -> Result<ArchiveStats, Error> {
+    Ok(ArchiveStats {
+        archive_id: *archive_id,
+        original_size: i64::try_from(stats.original_size)?,
+        compressed_size: i64::try_from(stats.compressed_size)?,
+        deduplicated_size: i64::try_from(stats.deduplicated_size)?,
+        nfiles: i64::try_from(stats.nfiles)?,
+        duration: duration.unwrap_or_default(),
+    })
+}
+
+fn to_str(path: &OsStr) -> Result<&str, Error> {
+    path.to_str()
+        .ok_or_else(|| Error::InvalidPath(path.to_string_lossy().into_owned()))
+}
+
+fn format_archive_stats(archive_stats: &ArchiveStats) -> String {
+    format!(
+        "{} original, {} compressed, {} deduplicated, {} file(s) in {:.1}s, ratio {}",
+        HumanBytes(archive_stats.original_size as u64),
+        HumanBytes(archive_stats.compressed_size as u64),
+        HumanBytes(archive_stats.deduplicated_size as u64),
+        archive_stats.nfiles,
+        archive_stats.duration,
+        format_ratio(archive_stats.original_size, archive_stats.deduplicated_size)
+    )
+}
+
+fn format_optional_archive_stats(archive_stats: Option<&ArchiveStats>) -> String {
+    archive_stats
+        .map(|archive_stats| format!(" ({})", format_archive_stats(archive_stats)))
+        .unwrap_or_default()
+}
+
+fn format_ratio(original_size: i64, deduplicated_size: i64) -> String {
+    if deduplicated_size > 0 {
+        format!("{:.2}", original_size as f64 / deduplicated_size as f64)
+    } else {
+        String::from("-")
+    }
+}
+
+fn format_size_delta(delta: i64) -> String {
+    format!(
+        "{}{}",
+        if delta < 0 { "-" } else { "+" },
+        HumanBytes(delta.unsigned_abs())
+    )
+}
+
+fn match_archive_directories<'a>(directories: &'a [Directory], name: &str) -> Vec<&'a Directory> {
+    match parse_archive_prefix(name) {
+        Some(prefix) => directories
+            .iter()
+            .filter(|directory| get_archive_prefix(&directory.path) == prefix)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn parse_archive_prefix(name: &str) -> Option<&str> {
+    let mut parts = name.rsplitn(3, '-');
+    let time = parts.next()?;
+    let date = parts.next()?;
+    let prefix = parts.next()?;
+    let is_digits = |s: &str, len: usize| s.len() == len && s.chars().all(|c| c.is_ascii_digit());
+    if is_digits(date, 8) && is_digits(time, 6) {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+fn get_archive_prefix(path: &str) -> String {
+    path.split(['/', '_'])
+        .map(any_ascii)
+        .collect::<Vec<String>>()
+        .join("-")
+        .replace(" ", "")
+        .replace("--", "-")
+        .trim_start_matches("-")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Retention, Root};
+    use crate::database;
+    use std::process;
+
+    fn create_tree(name: &str, files: &[&str]) -> String {
+        let path = env::temp_dir().join(format!("permafrust-{}-{}", name, process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(path.join("repo")).unwrap();
+        for file in files {
+            let file = path.join("root").join(file);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, file.to_string_lossy().as_bytes()).unwrap();
+        }
+        path.to_string_lossy().into_owned()
+    }
+
+    fn get_config() -> Config {
+        Config {
+            compression: Some(String::from("zstd,1")),
+            margin: 0.0,
+            ..Config::default()
+        }
+    }
+
+    fn find_archive_names(repo: &str) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(repo)
+            .unwrap()
+            .filter_map(|e| e.unwrap().file_name().into_string().ok())
+            .filter_map(|file_name| file_name.strip_suffix(".b3").map(String::from))
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn get_retention_policy(
+        root_directory_id: Option<&Uuid>,
+        directory_id: Option<&Uuid>,
+        keep_last: i32,
+    ) -> RetentionPolicy {
+        RetentionPolicy {
+            id: Uuid::new_v4(),
+            root_directory_id: root_directory_id.cloned(),
+            directory_id: directory_id.cloned(),
+            keep_within: None,
+            keep_last: Some(keep_last),
+            keep_daily: None,
+            keep_weekly: None,
+            keep_monthly: None,
+            keep_yearly: None,
+        }
+    }
+
+    async fn find_keep_last(
+        catalog: &mut dyn Catalog,
+        config: &Config,
+        directory: &Directory,
+    ) -> Option<i32> {
+        find_effective_retention_policy(catalog, config, directory)
+            .await
+            .unwrap()
+            .and_then(|retention_policy| retention_policy.keep_last)
+    }
+
+    async fn watch_and_scan(
+        catalog: &mut dyn Catalog,
+        archiver: &dyn Archiver,
+        path: &str,
+        config: &Config,
+    ) -> Vec<Directory> {
+        init(archiver, &format!("{}/repo", path), "none")
+            .await
+            .unwrap();
+        watch(catalog, &format!("{}/root", path), 1, fingerprint::CONTENT)
+            .await
+            .unwrap();
+        scan(catalog, config, false).await.unwrap();
+        catalog.find_directories().await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn scan_create_and_delete_archives() {
+        let path = create_tree("create", &["a/1", "a/b/2", "c/3"]);
+        let repo = &format!("{}/repo", path);
+        let mut catalog = database::establish_connection("memory:").await.unwrap();
+        let catalog = catalog.as_mut();
+        let archiver = archiver::new(archiver::TAR, None).unwrap();
+        let archiver = archiver.as_ref();
+        let config = get_config();
+
+        let directories = watch_and_scan(catalog, archiver, &path, &config).await;
+        let paths: Vec<&str> = directories.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![format!("{}/root/a", path), format!("{}/root/c", path)]
+        );
+        let directory = &directories[0];
+
+        let failures = create(
+            catalog,
+            archiver,
+            repo,
+            &directory.id,
+            &config,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(failures.is_empty());
+        let archives = catalog
+            .find_archives_by_directory_id(&directory.id)
+            .await
+            .unwrap();
+        assert_eq!(archives.len(), 1);
+        let archive = &archives[0];
+        assert_eq!(
+            parse_archive_prefix(&archive.name),
+            Some(get_archive_prefix(&directory.path).as_str())
+        );
+        assert_eq!(archive.blake3_hash, directory.blake3_hash);
+        assert_eq!(find_archive_names(repo), vec![archive.name.clone()]);
+        let mut manifest: Vec<String> = catalog
+            .find_manifest_entries_by_directory_id_and_archive_id(&directory.id, Some(&archive.id))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        manifest.sort();
+        assert_eq!(manifest, vec!["1", "b/2"]);
+
+        let failures = create(
+            catalog,
+            archiver,
+            repo,
+            &directory.id,
+            &config,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(failures.as_slice(), [(_, Error::Conflict(_))]));
+
+        delete(catalog, archiver, Some(repo), &archive.id, true)
+            .await
+            .unwrap();
+        assert_eq!(find_archive_names(repo).len(), 1);
+        delete(catalog, archiver, Some(repo), &archive.id, false)
+            .await
+            .unwrap();
+        assert!(find_archive_names(repo).is_empty());
+        assert!(catalog
+            .find_archives_by_directory_id(&directory.id)
+            .await
+            .unwrap()
+            .is_empty());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn prune_archives_by_retention_policy() {
+        let path = create_tree("prune", &["a/1"]);
+        let repo = &format!("{}/repo", path);
+        let mut catalog = database::establish_connection("memory:").await.unwrap();
+        let catalog = catalog.as_mut();
+        let archiver = archiver::new(archiver::TAR, None).unwrap();
+        let archiver = archiver.as_ref();
+        let mut config = get_config();
+        config.retention = Some(Retention {
+            keep_last: Some(3),
+            ..Retention::default()
+        });
+
+        let directory = &watch_and_scan(catalog, archiver, &path, &config).await[0];
+        create(
+            catalog,
+            archiver,
+            repo,
+            &directory.id,
+            &config,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        let latest = catalog
+            .find_latest_archive_by_directory_id(&directory.id)
+            .await
+            .unwrap()
+            .unwrap();
+        // Older copies of the archive, as if created on previous days
+        let prefix = get_archive_prefix(&directory.path);
+        for day in 1..=2 {
+            let name = format!("{}-2021010{}-120000", prefix, day);
+            for extension in &["tar.zst", "b3"] {
+                std::fs::copy(
+                    format!("{}/{}.{}", repo, latest.name, extension),
+                    format!("{}/{}.{}", repo, name, extension),
+                )
+                .unwrap();
+            }
+            catalog
+                .create_archive(&Archive {
+                    id: Uuid::new_v4(),
+                    name: name.clone(),
+                    archive_id: name,
+                    created_date: latest.created_date - chrono::Duration::days(3 - day),
+                    ..latest.clone()
+                })
+                .await
+                .unwrap();
+        }
+
+        // The configured retention keeps all three
+        prune(
+            catalog,
+            archiver,
+            Some(repo),
+            &directory.id,
+            &config,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(find_archive_names(repo).len(), 3);
+
+        // A directory policy in the catalog takes precedence over the configuration
+        set_retention_policy(catalog, &get_retention_policy(None, Some(&directory.id), 1))
+            .await
+            .unwrap();
+        prune(
+            catalog,
+            archiver,
+            Some(repo),
+            &directory.id,
+            &config,
+            true,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(find_archive_names(repo).len(), 3);
+        prune(
+            catalog,
+            archiver,
+            Some(repo),
+            &directory.id,
+            &config,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(find_archive_names(repo), vec![latest.name.clone()]);
+        let archives = catalog
+            .find_archives_by_directory_id(&directory.id)
+            .await
+            .unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archives[0].id, latest.id);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn get_archive_prefix_normalizes_paths() {
+        assert_eq!(get_archive_prefix("/home/me/Photos"), "home-me-photos");
+        assert_eq!(get_archive_prefix("/data/Été_2020"), "data-ete-2020");
+        assert_eq!(get_archive_prefix("/data/My Music"), "data-mymusic");
+        assert_eq!(get_archive_prefix("/data/a_/b"), "data-a-b");
+    }
+
+    #[test]
+    fn parse_archive_prefix_strips_timestamps() {
+        assert_eq!(
+            parse_archive_prefix("home-me-photos-20210102-030405"),
+            Some("home-me-photos")
+        );
+        assert_eq!(
+            parse_archive_prefix("photos-20210102-030405"),
+            Some("photos")
+        );
+        assert_eq!(parse_archive_prefix("home-me-photos"), None);
+        assert_eq!(parse_archive_prefix("photos-2021012-030405"), None);
+        assert_eq!(parse_archive_prefix("photos-20210102-03040x"), None);
+        assert_eq!(parse_archive_prefix("20210102-030405"), None);
+    }
+
+    fn get_bin(size: i64, avail: i64, used: &[i64]) -> Bin {
+        let directory = Directory {
+            id: Uuid::new_v4(),
+            path: String::from("/data"),
+            blake3_hash: String::from(UNKNOWN_HASH),
+            root_directory_id: Uuid::new_v4(),
+            fingerprint: String::from(fingerprint::CONTENT),
+            dirty: false,
+        };
+        Bin {
+            repository: Repository {
+                id: Uuid::new_v4(),
+                label: String::from("backup"),
+                location: String::from("/mnt/backup"),
+                repo_id: String::from("repo"),
+                encryption: String::from("none"),
+                last_seen_date: Utc::now().naive_utc(),
+                drive_id: None,
+            },
+            label: String::from("backup"),
+            size,
+            avail,
+            mounted: true,
+            directories: used.iter().map(|size| (directory.clone(), *size)).collect(),
+        }
+    }
+
+    #[test]
+    fn fits_keeps_margin_free() {
+        let bin = get_bin(1000, 500, &[]);
+        assert!(bin.fits(500, 0.0));
+        assert!(!bin.fits(501, 0.0));
+        assert!(bin.fits(450, 0.05));
+        assert!(!bin.fits(451, 0.05));
+    }
+
+    #[test]
+    fn fits_counts_assigned_directories() {
+        let bin = get_bin(1000, 500, &[200, 100]);
+        assert!(bin.fits(200, 0.0));
+        assert!(!bin.fits(201, 0.0));
+        assert!(!bin.fits(150, 0.1));
+        assert!(bin.fits(0, 0.2));
+        assert!(!get_bin(1000, 0, &[]).fits(0, 0.01));
+    }
+
+    #[async_std::test]
+    async fn find_effective_retention_policy_prefers_narrower_scopes() {
+        let path = create_tree("retention", &["a/1"]);
+        let mut catalog = database::establish_connection("memory:").await.unwrap();
+        let catalog = catalog.as_mut();
+        let archiver = archiver::new(archiver::TAR, None).unwrap();
+        let mut config = get_config();
+        let directory = &watch_and_scan(catalog, archiver.as_ref(), &path, &config).await[0];
+        assert_eq!(find_keep_last(catalog, &config, directory).await, None);
+        config.retention = Some(Retention {
+            keep_last: Some(5),
+            ..Retention::default()
+        });
+        assert_eq!(find_keep_last(catalog, &config, directory).await, Some(5));
+        let global = get_retention_policy(None, None, 4);
+        set_retention_policy(catalog, &global).await.unwrap();
+        assert_eq!(find_keep_last(catalog, &config, directory).await, Some(4));
+
+        // A configured root is narrower than the catalog default
+        let root = Root {
+            retention: Some(Retention {
+                keep_last: Some(3),
+                ..Retention::default()
+            }),
+            ..Root::default()
+        };
+        config.roots.insert(format!("{}/root", path), root);
+        assert_eq!(find_keep_last(catalog, &config, directory).await, Some(3));
+
+        let root_directory_id = &directory.root_directory_id;
+        set_retention_policy(
+            catalog,
+            &get_retention_policy(Some(root_directory_id), None, 2),
+        )
+        .await
+        .unwrap();
+        assert_eq!(find_keep_last(catalog, &config, directory).await, Some(2));
+        set_retention_policy(catalog, &get_retention_policy(None, Some(&directory.id), 1))
+            .await
+            .unwrap();
+        assert_eq!(find_keep_last(catalog, &config, directory).await, Some(1));
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn scan_handles_unknown_and_invalid_hashes() {
+        let path = create_tree("hashes", &["a/1"]);
+        let mut catalog = database::establish_connection("memory:").await.unwrap();
+        let catalog = catalog.as_mut();
+        let archiver = archiver::new(archiver::TAR, None).unwrap();
+        let config = get_config();
+        let directory = &watch_and_scan(catalog, archiver.as_ref(), &path, &config).await[0];
+
+        // Imported archives leave directories with an unknown hash
+        catalog
+            .update_directory(&directory.id, UNKNOWN_HASH, fingerprint::CONTENT)
+            .await
+            .unwrap();
+        scan(catalog, &config, false).await.unwrap();
+        let rescanned = catalog.find_directory_by_id(&directory.id).await.unwrap();
+        assert_eq!(rescanned.blake3_hash, directory.blake3_hash);
+
+        let mut manifest_entries = catalog
+            .find_manifest_entries_by_directory_id_and_archive_id(&directory.id, None)
+            .await
+            .unwrap();
+        manifest_entries[0].blake3_hash = Some(String::from("invalid"));
+        catalog
+            .delete_manifest_entries_by_directory_id(&directory.id)
+            .await
+            .unwrap();
+        catalog
+            .create_manifest_entries(&directory.id, &manifest_entries)
+            .await
+            .unwrap();
+        assert!(matches!(
+            scan(catalog, &config, true).await,
+            Err(Error::Io(_))
+        ));
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn reconcile_only_adopts_archives_of_a_single_directory() {
+        let path = create_tree("reconcile", &["a/1"]);
+        let repo = &format!("{}/repo", path);
+        let mut catalog = database::establish_connection("memory:").await.unwrap();
+        let catalog = catalog.as_mut();
+        let archiver = archiver::new(archiver::TAR, None).unwrap();
+        let archiver = archiver.as_ref();
+        let config = get_config();
+        let directory = &watch_and_scan(catalog, archiver, &path, &config).await[0];
+        create(
+            catalog,
+            archiver,
+            repo,
+            &directory.id,
+            &config,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        let archive = catalog
+            .find_latest_archive_by_directory_id(&directory.id)
+            .await
+            .unwrap()
+            .unwrap();
+        catalog.delete_archive(&archive.id).await.unwrap();
+        for extension in &["tar.zst", "b3"] {
+            std::fs::copy(
+                format!("{}/{}.{}", repo, archive.name, extension),
+                format!("{}/elsewhere-20210101-120000.{}", repo, extension),
+            )
+            .unwrap();
+        }
+
+        reconcile(catalog, archiver, repo, false).await.unwrap();
+        assert!(catalog
+            .find_archives_by_repo_id(&archive.repo_id)
+            .await
+            .unwrap()
+            .is_empty());
+        reconcile(catalog, archiver, repo, true).await.unwrap();
+        let archives = catalog
+            .find_archives_by_repo_id(&archive.repo_id)
+            .await
+            .unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archives[0].name, archive.name);
+        assert_eq!(archives[0].directory_id, Some(directory.id));
+        std::fs::remove_dir_all(path).unwrap();
+    }
 }
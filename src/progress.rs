@@ -0,0 +1,45 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressFinish, ProgressStyle};
+
+lazy_static! {
+    static ref MULTI_PROGRESS: MultiProgress = MultiProgress::new();
+}
+
+const BATCH_TEMPLATE: &str = "{prefix:.bold} [{bar:40}] {pos}/{len} directories ({eta})";
+const BYTES_TEMPLATE: &str =
+    "{prefix:.bold} [{bar:40}] {bytes}/{total_bytes} {msg} ({bytes_per_sec}, {eta})";
+const PERCENT_TEMPLATE: &str = "{prefix:.bold} [{bar:40}] {msg}";
+const PROGRESS_CHARS: &str = "=> ";
+
+pub fn batch(len: usize) -> ProgressBar {
+    if len < 2 {
+        return ProgressBar::hidden();
+    }
+    add(
+        ProgressBar::new(len as u64),
+        BATCH_TEMPLATE,
+        String::from("total"),
+    )
+}
+
+pub fn bytes(prefix: &str, len: u64) -> ProgressBar {
+    add(ProgressBar::new(len), BYTES_TEMPLATE, prefix.to_string())
+}
+
+pub fn percent(prefix: &str) -> ProgressBar {
+    add(ProgressBar::new(0), PERCENT_TEMPLATE, prefix.to_string())
+}
+
+pub fn println(message: &str) {
+    MULTI_PROGRESS.suspend(|| eprintln!("{}", message));
+}
+
+fn add(progress_bar: ProgressBar, template: &str, prefix: String) -> ProgressBar {
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template(template)
+            .unwrap()
+            .progress_chars(PROGRESS_CHARS),
+    );
+    progress_bar.set_prefix(prefix);
+    MULTI_PROGRESS.add(progress_bar.with_finish(ProgressFinish::AndClear))
+}
@@ -0,0 +1,458 @@
+use super::archiver::{
+    get_retention_options, parse_keep_within, Archive, Archiver, CreateOutput, Encryption,
+    InfoOutput, ListOutput, Repository, Stats,
+};
+use super::exclusion::Pattern;
+use super::model::RetentionPolicy;
+use async_std::fs;
+use async_std::io::{Error, ErrorKind};
+use async_std::path::Path;
+use async_std::process::{Command, ExitStatus, Stdio};
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use serde::Deserialize;
+use std::os::unix::process::ExitStatusExt;
+
+#[derive(Deserialize)]
+struct Config {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    id: String,
+    time: String,
+    paths: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    summary: Option<Summary>,
+}
+
+#[derive(Deserialize)]
+struct Summary {
+    #[serde(default)]
+    message_type: String,
+    snapshot_id: Option<String>,
+    total_files_processed: Option<u64>,
+    total_bytes_processed: Option<u64>,
+    total_duration: Option<f64>,
+    data_added: Option<u64>,
+    data_added_packed: Option<u64>,
+}
+
+impl Summary {
+    fn stats(&self) -> Option<Stats> {
+        let added = self.data_added_packed.or(self.data_added)?;
+        Some(Stats {
+            original_size: self.total_bytes_processed?,
+            compressed_size: added,
+            deduplicated_size: added,
+            nfiles: self.total_files_processed?,
+        })
+    }
+}
+
+pub struct Restic;
+
+#[async_trait]
+impl Archiver for Restic {
+    async fn init(&self, repo: &str, _encryption: &str) -> Result<(), Error> {
+        init(repo).await
+    }
+
+    async fn info(&self, repo: &str) -> Result<InfoOutput, Error> {
+        info(repo).await
+    }
+
+    async fn list(&self, repo: &str) -> Result<ListOutput, Error> {
+        list(repo).await
+    }
+
+    async fn info_archive(&self, repo: &str, name: &str) -> Result<Archive, Error> {
+        info_archive(repo, name).await
+    }
+
+    async fn create(
+        &self,
+        repo: &str,
+        prefix: &str,
+        path: &str,
+        patterns: &[String],
+        _compression: &str,
+        dry_run: bool,
+        _progress: &ProgressBar,
+    ) -> Result<CreateOutput, Error> {
+        create(repo, prefix, path, patterns, dry_run).await
+    }
+
+    fn reports_dry_run_stats(&self) -> bool {
+        true
+    }
+
+    async fn delete(&self, repo: &str, name: &str, dry_run: bool) -> Result<(), Error> {
+        delete(repo, name, dry_run).await
+    }
+
+    async fn prune(
+        &self,
+        repo: &str,
+        prefix: &str,
+        retention_policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        prune(repo, prefix, retention_policy, dry_run).await
+    }
+
+    async fn extract(
+        &self,
+        repo: &str,
+        name: &str,
+        path: &str,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        extract(repo, name, path, dry_run).await
+    }
+
+    async fn check(&self, repo: &str, name: &str, repair: bool) -> Result<(), Error> {
+        check(repo, name, repair).await
+    }
+
+    async fn transfer(
+        &self,
+        _repo: &str,
+        _other_repo: &str,
+        _name: Option<&str>,
+        _dry_run: bool,
+    ) -> Result<(), Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Transferring archives is only supported by borg 2",
+        ))
+    }
+}
+
+async fn init(repo: &str) -> Result<(), Error> {
+    if !Path::new(repo).is_dir().await {
+        fs::create_dir_all(repo).await?;
+    }
+    let args: Vec<&str> = vec!["init", "--repo", repo];
+    let mut command = Command::new("restic");
+    command.args(&args);
+    status(&mut command).await
+}
+
+async fn info(repo: &str) -> Result<InfoOutput, Error> {
+    let args: Vec<&str> = vec!["cat", "config", "--json", "--repo", repo];
+    let mut command = Command::new("restic");
+    command.args(&args);
+    let stdout = output(&mut command).await?;
+    let config: Config = serde_json::from_slice(stdout.as_slice())?;
+    Ok(InfoOutput {
+        encryption: Encryption {
+            mode: String::from("restic"),
+        },
+        repository: Repository { id: config.id },
+    })
+}
+
+async fn list(repo: &str) -> Result<ListOutput, Error> {
+    let repository = info(repo).await?.repository;
+    let archives = find_snapshots(repo)
+        .await?
+        .into_iter()
+        .filter(|snapshot| !snapshot.tags.is_empty())
+        .map(|snapshot| {
+            Ok(Archive {
+                start: parse_time(&snapshot.time)?,
+                name: snapshot.tags[0].clone(),
+                id: snapshot.id,
+                duration: None,
+                stats: None,
+            })
+        })
+        .collect::<Result<Vec<Archive>, Error>>()?;
+    Ok(ListOutput {
+        archives,
+        repository,
+    })
+}
+
+async fn info_archive(repo: &str, name: &str) -> Result<Archive, Error> {
+    let snapshot = find_snapshot_by_name(repo, name).await?;
+    Ok(Archive {
+        start: parse_time(&snapshot.time)?,
+        duration: None,
+        stats: snapshot.summary.as_ref().and_then(Summary::stats),
+        id: snapshot.id,
+        name: name.to_string(),
+    })
+}
+
+async fn create(
+    repo: &str,
+    prefix: &str,
+    path: &str,
+    patterns: &[String],
+    dry_run: bool,
+) -> Result<CreateOutput, Error> {
+    let start = Utc::now();
+    let name = format!("{}-{}", prefix, start.format("%Y%m%d-%H%M%S"));
+    let mut args: Vec<&str> = Vec::new();
+    args.push("backup");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--json");
+    args.push("--tag");
+    args.push(&name);
+    args.push("--tag");
+    args.push(prefix);
+    args.push("--exclude-caches");
+    let exclude_patterns = get_exclude_patterns(path, patterns);
+    for exclude_pattern in &exclude_patterns {
+        args.push("--exclude");
+        args.push(exclude_pattern);
+    }
+    args.push("--repo");
+    args.push(repo);
+    args.push(".");
+    let mut command = Command::new("restic");
+    command.current_dir(path).args(&args);
+    let stdout = output(&mut command).await?;
+    let summary = stdout
+        .split(|b| *b == b'\n')
+        .filter_map(|line| serde_json::from_slice::<Summary>(line).ok())
+        .find(|message| message.message_type == "summary")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing backup summary"))?;
+    Ok(CreateOutput {
+        archive: Archive {
+            start: start.with_timezone(&Local).naive_local(),
+            duration: summary.total_duration,
+            stats: summary.stats(),
+            id: summary.snapshot_id.unwrap_or_default(),
+            name,
+        },
+        repository: info(repo).await?.repository,
+    })
+}
+
+async fn delete(repo: &str, name: &str, dry_run: bool) -> Result<(), Error> {
+    let snapshot = find_snapshot_by_name(repo, name).await?;
+    let mut args: Vec<&str> = Vec::new();
+    args.push("forget");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--prune");
+    args.push("--repo");
+    args.push(repo);
+    args.push(&snapshot.id);
+    let mut command = Command::new("restic");
+    command.args(&args);
+    status(&mut command).await
+}
+
+async fn prune(
+    repo: &str,
+    prefix: &str,
+    retention_policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let keep_within = retention_policy
+        .keep_within
+        .as_deref()
+        .map(get_keep_within)
+        .transpose()?;
+    let options = get_retention_options(retention_policy, keep_within.as_deref());
+    let mut args: Vec<&str> = Vec::new();
+    args.push("forget");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--prune");
+    args.extend(options.iter().map(String::as_str));
+    args.push("--tag");
+    args.push(prefix);
+    args.push("--group-by");
+    args.push("");
+    args.push("--repo");
+    args.push(repo);
+    let mut command = Command::new("restic");
+    command.args(&args);
+    status(&mut command).await
+}
+
+async fn extract(repo: &str, name: &str, path: &str, dry_run: bool) -> Result<(), Error> {
+    if !Path::new(path).is_dir().await {
+        fs::create_dir_all(path).await?;
+    }
+    let snapshot = find_snapshot_by_name(repo, name).await?;
+    let snapshot_path = format!("{}:{}", snapshot.id, snapshot.paths[0]);
+    let mut args: Vec<&str> = Vec::new();
+    args.push("restore");
+    if dry_run {
+        args.push("--dry-run");
+    }
+    args.push("--target");
+    args.push(path);
+    args.push("--repo");
+    args.push(repo);
+    args.push(&snapshot_path);
+    let mut command = Command::new("restic");
+    command.args(&args);
+    status(&mut command).await
+}
+
+/// `restic check` cannot be limited to a snapshot, dumping it reads every
+/// blob it references instead, and restic verifies each one as it loads it.
+async fn check(repo: &str, name: &str, repair: bool) -> Result<(), Error> {
+    if repair {
+        let args: Vec<&str> = vec!["repair", "index", "--repo", repo];
+        let mut command = Command::new("restic");
+        command.args(&args);
+        status(&mut command).await?;
+    }
+    let snapshot = find_snapshot_by_name(repo, name).await?;
+    let args: Vec<&str> = vec![
+        "dump",
+        "--archive",
+        "tar",
+        "--repo",
+        repo,
+        &snapshot.id,
+        "/",
+    ];
+    let mut command = Command::new("restic");
+    command.args(&args).stdout(Stdio::null());
+    output(&mut command).await?;
+    Ok(())
+}
+
+async fn find_snapshots(repo: &str) -> Result<Vec<Snapshot>, Error> {
+    let args: Vec<&str> = vec!["snapshots", "--json", "--repo", repo];
+    let mut command = Command::new("restic");
+    command.args(&args);
+    let stdout = output(&mut command).await?;
+    let snapshots = serde_json::from_slice(stdout.as_slice())?;
+    Ok(snapshots)
+}
+
+async fn find_snapshot_by_name(repo: &str, name: &str) -> Result<Snapshot, Error> {
+    find_snapshots(repo)
+        .await?
+        .into_iter()
+        .find(|snapshot| snapshot.tags.first().map(String::as_str) == Some(name))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("No snapshot tagged {} in {}", name, repo),
+            )
+        })
+}
+
+/// Runs a command showing its output, keeping stderr to report failures.
+async fn status(command: &mut Command) -> Result<(), Error> {
+    command.stdout(Stdio::inherit());
+    output(command).await?;
+    Ok(())
+}
+
+async fn output(command: &mut Command) -> Result<Vec<u8>, Error> {
+    command.stderr(Stdio::piped());
+    debug!("{:?}", command);
+    let output = command.output().await?;
+    check_exit_status(output.status, &output.stderr)?;
+    Ok(output.stdout)
+}
+
+/// restic has no `w` unit and reads `m` and `y` as calendar months and
+/// years, so the interval is passed in hours to keep borg's meaning.
+fn get_keep_within(keep_within: &str) -> Result<String, Error> {
+    Ok(format!("{}h", parse_keep_within(keep_within)?.num_hours()))
+}
+
+fn check_exit_status(exit_status: ExitStatus, stderr: &[u8]) -> Result<(), Error> {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    match exit_status.code() {
+        Some(0) => Ok(()),
+        // Snapshot created, but some source files could not be read
+        Some(3) => {
+            warn!("restic completed with warnings: {}", stderr);
+            Ok(())
+        }
+        Some(code) if stderr.is_empty() => Err(Error::other(format!(
+            "restic failed with exit code {}",
+            code
+        ))),
+        Some(_) => Err(Error::other(stderr.to_string())),
+        None => Err(Error::new(
+            ErrorKind::Interrupted,
+            format!(
+                "restic was killed by signal {}",
+                exit_status.signal().unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveDateTime, Error> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Local).naive_local())
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))
+}
+
+fn get_exclude_patterns(path: &str, patterns: &[String]) -> Vec<String> {
+    // Restic matches unanchored globs at any depth and anchored ones against
+    // absolute paths, it cannot tell directories apart
+    patterns
+        .iter()
+        .filter_map(|line| Pattern::parse(line))
+        .map(|pattern| {
+            let mut exclude_pattern = String::new();
+            if pattern.negated {
+                exclude_pattern.push('!');
+            }
+            if pattern.anchored {
+                for c in path.trim_end_matches('/').chars() {
+                    if matches!(c, '\\' | '*' | '?' | '[') {
+                        exclude_pattern.push('\\');
+                    }
+                    exclude_pattern.push(c);
+                }
+                exclude_pattern.push('/');
+            }
+            exclude_pattern.push_str(&pattern.glob);
+            exclude_pattern
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_exclude_patterns_anchors_to_path() {
+        let patterns = vec![
+            "*.log".to_string(),
+            "!/keep.log".to_string(),
+            "a/**/b/".to_string(),
+        ];
+        assert_eq!(
+            get_exclude_patterns("/data/[x]/", &patterns),
+            vec!["*.log", "!/data/\\[x]/keep.log", "/data/\\[x]/a/**/b"]
+        );
+    }
+
+    #[test]
+    fn get_keep_within_converts_to_hours() {
+        assert_eq!(get_keep_within("12H").unwrap(), "12h");
+        assert_eq!(get_keep_within("1w").unwrap(), "168h");
+        assert_eq!(get_keep_within("1m").unwrap(), "744h");
+        assert_eq!(get_keep_within("1y").unwrap(), "8760h");
+        assert!(get_keep_within("1x").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use super::exclusion::Filter;
+use async_std::task;
+use log::warn;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::mem::MaybeUninit;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+const BLOCK_SIZE: u64 = 512;
+
+pub struct FilesystemSpace {
+    pub size: u64,
+    pub avail: u64,
+}
+
+#[derive(Default)]
+pub struct DirectorySize {
+    /// Sum of file lengths, which is what ends up in an archive before compression
+    pub apparent: u64,
+    /// Space actually used on disk, accounting for sparse files and block rounding
+    pub allocated: u64,
+}
+
+pub async fn filesystem(path: &str) -> Result<FilesystemSpace, Error> {
+    let c_path = CString::new(path).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    task::spawn_blocking(move || statvfs(&c_path))
+        .await
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+pub async fn directory(filter: &Filter) -> Result<DirectorySize, Error> {
+    let mut filter = filter.clone();
+    task::spawn_blocking(move || directory_size(&mut filter)).await
+}
+
+// statvfs field widths differ between platforms
+#[allow(clippy::useless_conversion)]
+fn statvfs(path: &CStr) -> Result<FilesystemSpace, Error> {
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    let fragment_size = u64::from(stat.f_frsize);
+    Ok(FilesystemSpace {
+        size: u64::from(stat.f_blocks) * fragment_size,
+        avail: u64::from(stat.f_bavail) * fragment_size,
+    })
+}
+
+fn directory_size(filter: &mut Filter) -> Result<DirectorySize, Error> {
+    let path = filter.path().to_path_buf();
+    fs::symlink_metadata(&path)?;
+    // Files with several links are counted once, like du does
+    let hard_links = Mutex::new(HashSet::new());
+    Ok(WalkDir::new(path)
+        .into_iter()
+        .filter_entry(|e| !filter.is_excluded(e))
+        .par_bridge()
+        .filter_map(|entry| match entry.and_then(|entry| entry.metadata()) {
+            Ok(metadata) => Some(metadata),
+            Err(error) => {
+                warn!("Skipping {}", error);
+                None
+            }
+        })
+        .filter(|metadata| {
+            metadata.is_dir()
+                || metadata.nlink() < 2
+                || hard_links
+                    .lock()
+                    .unwrap()
+                    .insert((metadata.dev(), metadata.ino()))
+        })
+        .map(|metadata| DirectorySize {
+            apparent: metadata.len(),
+            allocated: metadata.blocks() * BLOCK_SIZE,
+        })
+        .reduce(DirectorySize::default, |a, b| DirectorySize {
+            apparent: a.apparent + b.apparent,
+            allocated: a.allocated + b.allocated,
+        }))
+}
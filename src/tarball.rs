@@ -0,0 +1,766 @@
+use super::archiver::{
+    parse_keep_within, Archive, Archiver, CreateOutput, Encryption, InfoOutput, ListOutput,
+    Repository, Stats,
+};
+use super::exclusion::Filter;
+use super::model::RetentionPolicy;
+use async_std::fs;
+use async_std::io::{Error, ErrorKind};
+use async_std::path::Path;
+use async_std::prelude::*;
+use async_std::task;
+use async_trait::async_trait;
+use blake3::Hasher;
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use indicatif::ProgressBar;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+const CONFIG: &str = "permafrust.json";
+const MANIFEST_EXTENSION: &str = "b3";
+const ARCHIVE_EXTENSION: &str = "tar.zst";
+const DATETIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Deserialize, Serialize)]
+struct Config {
+    id: String,
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+struct VolumeWriter {
+    path: PathBuf,
+    volume_size: Option<u64>,
+    index: usize,
+    written: u64,
+    total: u64,
+    file: Option<File>,
+}
+
+impl VolumeWriter {
+    fn new(path: PathBuf, volume_size: Option<u64>) -> VolumeWriter {
+        VolumeWriter {
+            path,
+            volume_size,
+            index: 0,
+            written: 0,
+            total: 0,
+            file: None,
+        }
+    }
+
+    fn volume_path(&self, index: usize) -> PathBuf {
+        get_volume_path(&self.path, self.volume_size, index)
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(volume_size) = self.volume_size {
+            if self.written == volume_size {
+                self.file = None;
+                self.index += 1;
+                self.written = 0;
+            }
+        }
+        if self.file.is_none() {
+            self.file = Some(File::create(self.volume_path(self.index))?);
+        }
+        let len = match self.volume_size {
+            Some(volume_size) => buf.len().min((volume_size - self.written) as usize),
+            None => buf.len(),
+        };
+        let n = self.file.as_mut().unwrap().write(&buf[..len])?;
+        self.written += n as u64;
+        self.total += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub struct Tarball {
+    pub volume_size: Option<u64>,
+}
+
+#[async_trait]
+impl Archiver for Tarball {
+    async fn init(&self, repo: &str, encryption: &str) -> Result<(), Error> {
+        init(repo, encryption).await
+    }
+
+    async fn info(&self, repo: &str) -> Result<InfoOutput, Error> {
+        info(repo).await
+    }
+
+    async fn list(&self, repo: &str) -> Result<ListOutput, Error> {
+        list(repo).await
+    }
+
+    async fn info_archive(&self, _repo: &str, _name: &str) -> Result<Archive, Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Archive statistics are only recorded at creation with tar",
+        ))
+    }
+
+    async fn create(
+        &self,
+        repo: &str,
+        prefix: &str,
+        path: &str,
+        patterns: &[String],
+        compression: &str,
+        dry_run: bool,
+        progress: &ProgressBar,
+    ) -> Result<CreateOutput, Error> {
+        create(
+            repo,
+            prefix,
+            path,
+            patterns,
+            compression,
+            self.volume_size,
+            dry_run,
+            progress,
+        )
+        .await
+    }
+
+    async fn delete(&self, repo: &str, name: &str, dry_run: bool) -> Result<(), Error> {
+        delete(repo, name, dry_run).await
+    }
+
+    async fn prune(
+        &self,
+        repo: &str,
+        prefix: &str,
+        retention_policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        prune(repo, prefix, retention_policy, dry_run).await
+    }
+
+    async fn extract(
+        &self,
+        repo: &str,
+        name: &str,
+        path: &str,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        extract(repo, name, path, dry_run).await
+    }
+
+    async fn check(&self, repo: &str, name: &str, repair: bool) -> Result<(), Error> {
+        check(repo, name, repair).await
+    }
+
+    async fn transfer(
+        &self,
+        _repo: &str,
+        _other_repo: &str,
+        _name: Option<&str>,
+        _dry_run: bool,
+    ) -> Result<(), Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Transferring archives is only supported by borg 2",
+        ))
+    }
+}
+
+async fn init(repo: &str, encryption: &str) -> Result<(), Error> {
+    if encryption != "none" {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Tarball repositories do not support encryption",
+        ));
+    }
+    if !Path::new(repo).is_dir().await {
+        fs::create_dir_all(repo).await?;
+    }
+    let config_path = Path::new(repo).join(CONFIG);
+    if config_path.is_file().await {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("Repository {} already exists", repo),
+        ));
+    }
+    let config = Config {
+        id: Uuid::new_v4().to_simple().to_string(),
+    };
+    fs::write(config_path, serde_json::to_vec(&config)?).await?;
+    Ok(())
+}
+
+async fn info(repo: &str) -> Result<InfoOutput, Error> {
+    let config: Config = serde_json::from_slice(&fs::read(Path::new(repo).join(CONFIG)).await?)?;
+    Ok(InfoOutput {
+        encryption: Encryption {
+            mode: String::from("none"),
+        },
+        repository: Repository { id: config.id },
+    })
+}
+
+async fn list(repo: &str) -> Result<ListOutput, Error> {
+    let repository = info(repo).await?.repository;
+    let mut archives: Vec<Archive> = Vec::new();
+    for name in find_archive_names(repo).await? {
+        if let Some(start) = parse_archive_date(&name) {
+            archives.push(Archive {
+                id: name.clone(),
+                name,
+                start: Local.from_utc_datetime(&start).naive_local(),
+                duration: None,
+                stats: None,
+            });
+        }
+    }
+    archives.sort_by_key(|a| a.start);
+    Ok(ListOutput {
+        archives,
+        repository,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn create(
+    repo: &str,
+    prefix: &str,
+    path: &str,
+    patterns: &[String],
+    compression: &str,
+    volume_size: Option<u64>,
+    dry_run: bool,
+    progress: &ProgressBar,
+) -> Result<CreateOutput, Error> {
+    let repository = info(repo).await?.repository;
+    let start = Utc::now().naive_utc();
+    let name = format!("{}-{}", prefix, start.format(DATETIME_FORMAT));
+    let level = get_compression_level(compression);
+    let mut stats: Option<Stats> = None;
+    if !dry_run {
+        let archive_path = get_archive_path(repo, &name);
+        let manifest_path = get_manifest_path(repo, &name);
+        let mut filter = Filter::new(path, patterns)?;
+        let progress = progress.clone();
+        stats = Some(
+            task::spawn_blocking(move || {
+                write_archive(
+                    &mut filter,
+                    archive_path,
+                    manifest_path,
+                    level,
+                    volume_size,
+                    &progress,
+                )
+            })
+            .await?,
+        );
+    }
+    let duration = Utc::now().naive_utc() - start;
+    Ok(CreateOutput {
+        archive: Archive {
+            id: name.clone(),
+            name,
+            start: Local.from_utc_datetime(&start).naive_local(),
+            duration: Some(duration.num_milliseconds() as f64 / 1000.0),
+            stats,
+        },
+        repository,
+    })
+}
+
+async fn delete(repo: &str, name: &str, dry_run: bool) -> Result<(), Error> {
+    for path in find_archive_files(repo, name).await? {
+        info!("Deleting {:?}", path);
+        if !dry_run {
+            fs::remove_file(path).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn prune(
+    repo: &str,
+    prefix: &str,
+    retention_policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let mut archives: Vec<(String, NaiveDateTime)> = find_archive_names(repo)
+        .await?
+        .into_iter()
+        .filter(|name| name.starts_with(&format!("{}-", prefix)))
+        .filter_map(|name| {
+            let date = parse_archive_date(&name)?;
+            if name.len() == prefix.len() + 16 {
+                Some((name, date))
+            } else {
+                None
+            }
+        })
+        .collect();
+    archives.sort_by_key(|a| Reverse(a.1));
+    let kept = get_kept_archives(&archives, retention_policy)?;
+    for (name, _) in &archives {
+        if kept.contains(name) {
+            println!("Keeping archive: {}", name);
+        } else {
+            println!("Pruning archive: {}", name);
+            delete(repo, name, dry_run).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn extract(repo: &str, name: &str, path: &str, dry_run: bool) -> Result<(), Error> {
+    if !Path::new(path).is_dir().await {
+        fs::create_dir_all(path).await?;
+    }
+    let volumes = find_volumes(repo, name).await?;
+    let path = PathBuf::from(path);
+    task::spawn_blocking(move || {
+        let mut archive = tar::Archive::new(zstd::Decoder::new(open_volumes(&volumes)?)?);
+        if dry_run {
+            for entry in archive.entries()? {
+                println!("{}", entry?.path()?.display());
+            }
+            Ok(())
+        } else {
+            archive.set_preserve_permissions(true);
+            archive.unpack(path)
+        }
+    })
+    .await
+}
+
+async fn check(repo: &str, name: &str, repair: bool) -> Result<(), Error> {
+    if repair {
+        warn!("Tarball archives cannot be repaired, checking only");
+    }
+    let volumes = find_volumes(repo, name).await?;
+    let manifest = read_manifest(&get_manifest_path(repo, name))?;
+    let name = name.to_string();
+    task::spawn_blocking(move || {
+        let mut archive = tar::Archive::new(zstd::Decoder::new(open_volumes(&volumes)?)?);
+        let mut checked: HashSet<String> = HashSet::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?.to_string_lossy().to_string();
+            let mut reader = HashingReader {
+                inner: entry,
+                hasher: Hasher::new(),
+            };
+            io::copy(&mut reader, &mut io::sink())?;
+            let hash = reader.hasher.finalize().to_hex().to_string();
+            if manifest.get(&path) != Some(&hash) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Checksum mismatch for {} in {}", path, name),
+                ));
+            }
+            checked.insert(path);
+        }
+        if let Some(path) = manifest.keys().find(|path| !checked.contains(*path)) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Missing {} in {}", path, name),
+            ));
+        }
+        println!("Archive {} is consistent", name);
+        Ok(())
+    })
+    .await
+}
+
+/// Writes under temporary names, renamed once complete, so that an
+/// interrupted archive is never listed.
+fn write_archive(
+    filter: &mut Filter,
+    archive_path: PathBuf,
+    manifest_path: PathBuf,
+    level: i32,
+    volume_size: Option<u64>,
+    progress: &ProgressBar,
+) -> Result<Stats, Error> {
+    let tmp_archive_path = get_temporary_path(&archive_path);
+    let tmp_manifest_path = get_temporary_path(&manifest_path);
+    match write_volumes(
+        filter,
+        &tmp_archive_path,
+        &tmp_manifest_path,
+        level,
+        volume_size,
+        progress,
+    ) {
+        Ok((stats, volumes)) => {
+            for index in 0..volumes {
+                std::fs::rename(
+                    get_volume_path(&tmp_archive_path, volume_size, index),
+                    get_volume_path(&archive_path, volume_size, index),
+                )?;
+            }
+            std::fs::rename(tmp_manifest_path, manifest_path)?;
+            Ok(stats)
+        }
+        Err(error) => {
+            let _ = std::fs::remove_file(tmp_manifest_path);
+            for index in 0.. {
+                let volume = get_volume_path(&tmp_archive_path, volume_size, index);
+                if std::fs::remove_file(volume).is_err() {
+                    break;
+                }
+            }
+            Err(error)
+        }
+    }
+}
+
+fn write_volumes(
+    filter: &mut Filter,
+    archive_path: &std::path::Path,
+    manifest_path: &std::path::Path,
+    level: i32,
+    volume_size: Option<u64>,
+    progress: &ProgressBar,
+) -> Result<(Stats, usize), Error> {
+    let mut original_size = 0;
+    let mut nfiles = 0;
+    let writer = VolumeWriter::new(archive_path.to_path_buf(), volume_size);
+    let mut builder = tar::Builder::new(zstd::Encoder::new(writer, level)?);
+    builder.follow_symlinks(false);
+    let mut manifest = File::create(manifest_path)?;
+    let path = filter.path().to_path_buf();
+    for e in WalkDir::new(&path)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !filter.is_excluded(e))
+        .filter_map(|v| v.ok())
+    {
+        let relative_path = e.path().strip_prefix(&path).unwrap();
+        if e.file_type().is_file() {
+            let metadata = e.metadata()?;
+            let mut header = tar::Header::new_gnu();
+            header.set_metadata(&metadata);
+            let mut reader = HashingReader {
+                inner: File::open(e.path())?,
+                hasher: Hasher::new(),
+            };
+            builder.append_data(&mut header, relative_path, &mut reader)?;
+            writeln!(
+                manifest,
+                "{}  {}",
+                reader.hasher.finalize().to_hex(),
+                relative_path.display()
+            )?;
+            progress.inc(metadata.len());
+            original_size += metadata.len();
+            nfiles += 1;
+        } else {
+            builder.append_path_with_name(e.path(), relative_path)?;
+        }
+    }
+    let mut writer = builder.into_inner()?.finish()?;
+    writer.flush()?;
+    debug!("Wrote {} volume(s)", writer.index + 1);
+    Ok((
+        Stats {
+            original_size,
+            compressed_size: writer.total,
+            deduplicated_size: writer.total,
+            nfiles,
+        },
+        writer.index + 1,
+    ))
+}
+
+fn read_manifest(manifest_path: &std::path::Path) -> Result<HashMap<String, String>, Error> {
+    let mut manifest: HashMap<String, String> = HashMap::new();
+    for line in BufReader::new(File::open(manifest_path)?).lines() {
+        let line = line?;
+        if let Some((hash, path)) = line.split_once("  ") {
+            manifest.insert(path.to_string(), hash.to_string());
+        }
+    }
+    Ok(manifest)
+}
+
+fn open_volumes(volumes: &[PathBuf]) -> Result<Box<dyn Read>, Error> {
+    let mut reader: Box<dyn Read> = Box::new(io::empty());
+    for volume in volumes {
+        reader = Box::new(reader.chain(File::open(volume)?));
+    }
+    Ok(reader)
+}
+
+async fn find_archive_names(repo: &str) -> Result<Vec<String>, Error> {
+    let suffix = format!(".{}", MANIFEST_EXTENSION);
+    let mut names: Vec<String> = Vec::new();
+    let mut entries = fs::read_dir(repo).await?;
+    while let Some(e) = entries.next().await {
+        let file_name = e?.file_name().to_string_lossy().to_string();
+        if let Some(name) = file_name.strip_suffix(&suffix) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+async fn find_archive_files(repo: &str, name: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut files = find_volumes(repo, name).await?;
+    files.push(get_manifest_path(repo, name));
+    Ok(files)
+}
+
+async fn find_volumes(repo: &str, name: &str) -> Result<Vec<PathBuf>, Error> {
+    let archive_path = get_archive_path(repo, name);
+    if Path::new(&archive_path).is_file().await {
+        return Ok(vec![archive_path]);
+    }
+    let mut volumes: Vec<PathBuf> = Vec::new();
+    loop {
+        let volume = PathBuf::from(format!("{}.{:03}", archive_path.display(), volumes.len()));
+        if !Path::new(&volume).is_file().await {
+            break;
+        }
+        volumes.push(volume);
+    }
+    if volumes.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No archive {} in {}", name, repo),
+        ));
+    }
+    Ok(volumes)
+}
+
+fn get_archive_path(repo: &str, name: &str) -> PathBuf {
+    PathBuf::from(repo).join(format!("{}.{}", name, ARCHIVE_EXTENSION))
+}
+
+fn get_manifest_path(repo: &str, name: &str) -> PathBuf {
+    PathBuf::from(repo).join(format!("{}.{}", name, MANIFEST_EXTENSION))
+}
+
+fn get_volume_path(path: &std::path::Path, volume_size: Option<u64>, index: usize) -> PathBuf {
+    match volume_size {
+        Some(_) => PathBuf::from(format!("{}.{:03}", path.display(), index)),
+        None => path.to_path_buf(),
+    }
+}
+
+fn get_temporary_path(path: &std::path::Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".tmp");
+    PathBuf::from(path)
+}
+
+fn get_compression_level(compression: &str) -> i32 {
+    compression
+        .rsplit(',')
+        .next()
+        .and_then(|level| level.parse::<i32>().ok())
+        .unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL)
+}
+
+fn parse_archive_date(name: &str) -> Option<NaiveDateTime> {
+    let date = name.get(name.len().checked_sub(15)?..)?;
+    NaiveDateTime::parse_from_str(date, DATETIME_FORMAT).ok()
+}
+
+fn get_kept_archives(
+    archives: &[(String, NaiveDateTime)],
+    retention_policy: &RetentionPolicy,
+) -> Result<HashSet<String>, Error> {
+    let mut kept: HashSet<String> = HashSet::new();
+    if let Some(keep_within) = &retention_policy.keep_within {
+        let oldest = Utc::now().naive_utc() - parse_keep_within(keep_within)?;
+        for (name, date) in archives {
+            if date > &oldest {
+                kept.insert(name.clone());
+            }
+        }
+    }
+    if let Some(keep_last) = retention_policy.keep_last {
+        for (name, _) in archives.iter().take(keep_last as usize) {
+            kept.insert(name.clone());
+        }
+    }
+    let rules = [
+        (retention_policy.keep_daily, "%Y-%m-%d"),
+        (retention_policy.keep_weekly, "%G-%V"),
+        (retention_policy.keep_monthly, "%Y-%m"),
+        (retention_policy.keep_yearly, "%Y"),
+    ];
+    for (keep, format) in rules.iter() {
+        if let Some(keep) = keep {
+            let mut last_period: Option<String> = None;
+            let mut count = 0;
+            for (name, date) in archives {
+                if count == *keep {
+                    break;
+                }
+                // Periods follow local time, as with borg
+                let period = Local.from_utc_datetime(date).format(format).to_string();
+                if last_period.as_ref() != Some(&period) {
+                    last_period = Some(period);
+                    if kept.insert(name.clone()) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+    }
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn empty_retention_policy() -> RetentionPolicy {
+        RetentionPolicy {
+            id: Uuid::new_v4(),
+            root_directory_id: None,
+            directory_id: None,
+            keep_within: None,
+            keep_last: None,
+            keep_daily: None,
+            keep_weekly: None,
+            keep_monthly: None,
+            keep_yearly: None,
+        }
+    }
+
+    /// Newest first, as prune sorts them, around noon so that the local day
+    /// is the UTC one in most time zones.
+    fn archives(dates: &[(i32, u32, u32, u32)]) -> Vec<(String, NaiveDateTime)> {
+        dates
+            .iter()
+            .map(|(year, month, day, hour)| {
+                let date = NaiveDate::from_ymd(*year, *month, *day).and_hms(*hour, 0, 0);
+                (format!("a-{}", date.format(DATETIME_FORMAT)), date)
+            })
+            .collect()
+    }
+
+    fn sorted(kept: HashSet<String>) -> Vec<String> {
+        let mut kept: Vec<String> = kept.into_iter().collect();
+        kept.sort();
+        kept
+    }
+
+    #[test]
+    fn get_kept_archives_keeps_nothing_without_rules() {
+        let archives = archives(&[(2021, 3, 2, 12), (2021, 3, 1, 12)]);
+        assert!(get_kept_archives(&archives, &empty_retention_policy())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn get_kept_archives_keeps_last() {
+        let archives = archives(&[(2021, 3, 3, 12), (2021, 3, 2, 12), (2021, 3, 1, 12)]);
+        let retention_policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..empty_retention_policy()
+        };
+        assert_eq!(
+            sorted(get_kept_archives(&archives, &retention_policy).unwrap()),
+            vec!["a-20210302-120000", "a-20210303-120000"]
+        );
+    }
+
+    #[test]
+    fn get_kept_archives_keeps_newest_per_period() {
+        let archives = archives(&[
+            (2021, 3, 2, 12),
+            (2021, 3, 2, 11),
+            (2021, 3, 1, 12),
+            (2021, 2, 27, 12),
+            (2020, 12, 31, 12),
+        ]);
+        let retention_policy = RetentionPolicy {
+            keep_daily: Some(2),
+            ..empty_retention_policy()
+        };
+        assert_eq!(
+            sorted(get_kept_archives(&archives, &retention_policy).unwrap()),
+            vec!["a-20210301-120000", "a-20210302-120000"]
+        );
+        let retention_policy = RetentionPolicy {
+            keep_monthly: Some(2),
+            keep_yearly: Some(2),
+            ..empty_retention_policy()
+        };
+        assert_eq!(
+            sorted(get_kept_archives(&archives, &retention_policy).unwrap()),
+            vec![
+                "a-20201231-120000",
+                "a-20210227-120000",
+                "a-20210302-120000"
+            ]
+        );
+    }
+
+    #[test]
+    fn get_kept_archives_skips_archives_kept_by_earlier_rules() {
+        let archives = archives(&[(2021, 3, 3, 12), (2021, 3, 2, 12), (2021, 3, 1, 12)]);
+        let retention_policy = RetentionPolicy {
+            keep_last: Some(1),
+            keep_daily: Some(1),
+            ..empty_retention_policy()
+        };
+        assert_eq!(
+            sorted(get_kept_archives(&archives, &retention_policy).unwrap()),
+            vec!["a-20210302-120000", "a-20210303-120000"]
+        );
+    }
+
+    #[test]
+    fn get_kept_archives_keeps_within() {
+        let now = Utc::now().naive_utc();
+        let archives = vec![
+            (String::from("recent"), now - Duration::hours(1)),
+            (String::from("old"), now - Duration::days(3)),
+        ];
+        let retention_policy = RetentionPolicy {
+            keep_within: Some(String::from("2d")),
+            ..empty_retention_policy()
+        };
+        assert_eq!(
+            sorted(get_kept_archives(&archives, &retention_policy).unwrap()),
+            vec!["recent"]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub struct Changes {
+    pub paths: Vec<PathBuf>,
+    pub overflowed: bool,
+}
+
+pub struct Watcher {
+    inotify: Inotify,
+    paths: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+}
+
+impl Watcher {
+    pub fn new() -> Result<Watcher, Error> {
+        Ok(Watcher {
+            inotify: Inotify::init()?,
+            paths: HashMap::new(),
+            buffer: vec![0; 4096],
+        })
+    }
+
+    pub fn add_recursive(&mut self, path: &Path) {
+        for e in WalkDir::new(path)
+            .into_iter()
+            .filter_map(|v| v.ok())
+            .filter(|e| e.file_type().is_dir())
+        {
+            match self.inotify.add_watch(
+                e.path(),
+                WatchMask::MODIFY
+                    | WatchMask::ATTRIB
+                    | WatchMask::CREATE
+                    | WatchMask::DELETE
+                    | WatchMask::MOVE,
+            ) {
+                Ok(wd) => {
+                    debug!("Watching {:?}", e.path());
+                    self.paths.insert(wd, e.path().to_path_buf());
+                }
+                Err(error) => warn!("Failed to watch {:?}: {}", e.path(), error),
+            }
+        }
+    }
+
+    /// Drains the pending events, `overflowed` reports that the kernel dropped
+    /// some, so that changes may be missing.
+    pub fn read(&mut self) -> Result<Changes, Error> {
+        let mut changes = Changes {
+            paths: Vec::new(),
+            overflowed: false,
+        };
+        let mut created_directories: Vec<PathBuf> = Vec::new();
+        loop {
+            let mut events = self.inotify.read_events(&mut self.buffer)?.peekable();
+            if events.peek().is_none() {
+                break;
+            }
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    changes.overflowed = true;
+                    continue;
+                }
+                if event.mask.contains(EventMask::IGNORED) {
+                    self.paths.remove(&event.wd);
+                    continue;
+                }
+                let path = match (self.paths.get(&event.wd), event.name) {
+                    (Some(path), Some(name)) => path.join(name),
+                    (Some(path), None) => path.clone(),
+                    (None, _) => continue,
+                };
+                if event.mask.contains(EventMask::ISDIR)
+                    && (event.mask.contains(EventMask::CREATE)
+                        || event.mask.contains(EventMask::MOVED_TO))
+                {
+                    created_directories.push(path.clone());
+                }
+                changes.paths.push(path);
+            }
+        }
+        for path in created_directories {
+            self.add_recursive(&path);
+        }
+        Ok(changes)
+    }
+}